@@ -0,0 +1,160 @@
+//! Helpers for opening TAPE files directly from ZIP archives.
+//!
+//! Only the TAP entries are loaded, the snapshots are not supported, as there is no
+//! snapshot loader to pass them to.
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+use crate::tapefile::TapeFile;
+
+/// Extensions of the archive entries we know how to load.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["tap"];
+/// The largest archive entry we load, far more than any real tape needs.
+pub const MAX_ENTRY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Returns `true` if the `path` looks like a ZIP archive.
+pub fn is_archive_path<P: AsRef<Path>>(path: P) -> bool {
+    has_extension(path.as_ref(), "zip")
+}
+
+/// Returns `true` if the `name` of the archive entry is something we can load.
+pub fn is_supported_entry(name: &str) -> bool {
+    let path = Path::new(name);
+    SUPPORTED_EXTENSIONS.iter().any(|ext| has_extension(path, ext))
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension().and_then(|e| e.to_str())
+                    .map(|e| e.eq_ignore_ascii_case(ext))
+                    .unwrap_or(false)
+}
+
+/// Splits the command line form of `ARCHIVE.zip#ENTRY` into the archive path and
+/// the optional entry name.
+pub fn split_archive_path(arg: &str) -> (&str, Option<&str>) {
+    if let Some(pos) = arg.rfind('#') {
+        let (path, entry) = (&arg[..pos], &arg[pos + 1..]);
+        if is_archive_path(path) {
+            return (path, if entry.is_empty() { None } else { Some(entry) })
+        }
+    }
+    (arg, None)
+}
+
+/// Returns the names of all the supported entries found in the archive
+/// in the order they are stored.
+pub fn list_supported_entries<R: Read + Seek>(
+        archive: &mut ZipArchive<R>
+    ) -> io::Result<Vec<String>>
+{
+    let mut entries = Vec::new();
+    for index in 0..archive.len() {
+        let file = archive.by_index_raw(index)?;
+        if file.is_file() && is_supported_entry(file.name()) {
+            entries.push(file.name().to_string());
+        }
+    }
+    Ok(entries)
+}
+
+/// Opens the ZIP archive and returns it together with the names of all
+/// the supported entries.
+pub fn open_archive<P: AsRef<Path>>(
+        path: P
+    ) -> io::Result<(ZipArchive<File>, Vec<String>)>
+{
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let entries = list_supported_entries(&mut archive)?;
+    Ok((archive, entries))
+}
+
+/// Selects the entry by `name` (case insensitive) or the first one if `name` is `None`.
+pub fn select_entry<'a>(entries: &'a [String], name: Option<&str>) -> io::Result<&'a str> {
+    match name {
+        Some(name) => entries.iter().find(|e| e.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound,
+                            format!("no such entry in the archive: {}, choose from: {}",
+                                    name, entries.join(", ")))),
+        None => entries.first().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound,
+                            "no supported entries in the archive"))
+    }.map(String::as_str)
+}
+
+/// Reads the entry from the archive into memory.
+///
+/// The entries larger than [MAX_ENTRY_SIZE] are rejected, the size stored
+/// in the archive is not trusted.
+pub fn read_archive_entry<R: Read + Seek>(
+        archive: &mut ZipArchive<R>,
+        name: &str
+    ) -> io::Result<TapeFile>
+{
+    let file = archive.by_name(name)?;
+    let mut data = Vec::new();
+    file.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_ENTRY_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("the archive entry: {} is too large", name)))
+    }
+    Ok(TapeFile::Archived(Cursor::new(data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{ZipWriter, write::FileOptions, CompressionMethod};
+
+    // builds the archive in memory from the named entries
+    fn archive(entries: &[(&str, &[u8])]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.add_directory("dir.tap/", options).unwrap();
+        for (name, data) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        ZipArchive::new(zip.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn archive_path_is_split() {
+        assert_eq!(split_archive_path("games.zip#game.tap"), ("games.zip", Some("game.tap")));
+        assert_eq!(split_archive_path("games.ZIP#"), ("games.ZIP", None));
+        assert_eq!(split_archive_path("games.zip"), ("games.zip", None));
+        assert_eq!(split_archive_path("tape#1.tap"), ("tape#1.tap", None));
+    }
+
+    #[test]
+    fn entries_are_listed_and_read() {
+        let mut archive = archive(&[
+            ("readme.txt", b"read me"),
+            ("one/GAME.TAP", b"\x13\x00tape data"),
+            ("game.z80", b"snapshot"),
+            ("two.tap", b"")
+        ]);
+        let entries = list_supported_entries(&mut archive).unwrap();
+        assert_eq!(entries, ["one/GAME.TAP", "two.tap"]);
+        assert_eq!(select_entry(&entries, None).unwrap(), "one/GAME.TAP");
+        assert_eq!(select_entry(&entries, Some("TWO.tap")).unwrap(), "two.tap");
+        let err = select_entry(&entries, Some("game.z80")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(select_entry(&[], None).is_err());
+
+        match read_archive_entry(&mut archive, "one/GAME.TAP").unwrap() {
+            TapeFile::Archived(cursor) => assert_eq!(cursor.into_inner(), b"\x13\x00tape data"),
+            tape => panic!("unexpected tape: {:?}", tape)
+        }
+        assert!(read_archive_entry(&mut archive, "missing.tap").is_err());
+    }
+
+    #[test]
+    fn too_large_entry_is_rejected() {
+        let data = vec![0; MAX_ENTRY_SIZE as usize + 1];
+        let mut archive = archive(&[("large.tap", &data), ("max.tap", &data[1..])]);
+        let err = read_archive_entry(&mut archive, "large.tap").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(read_archive_entry(&mut archive, "max.tap").is_ok());
+    }
+}
@@ -0,0 +1,131 @@
+//! The estimation of the audio carousel underruns and overruns.
+//!
+//! The carousel doesn't tell when the audio thread plays the frames, so the number
+//! of the buffered frames is estimated from the times the frames are sent, assuming
+//! the frames are played at the steady rate:
+//!
+//! * an underrun is counted when more time passed since the previous frame was sent,
+//!   than it takes to play the buffered frames, so the audio thread must have played silence,
+//! * an overrun is counted when sending the frame had to wait for the audio thread
+//!   to release a buffer, so the emulation is ahead of the audio and the latency is at its maximum,
+//!   unless the audio paces the emulation, and the waiting is expected.
+use core::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+/// How long sending the frame may take before it's counted as an overrun.
+pub const OVERRUN_WAIT: Duration = Duration::from_millis(2);
+/// How often the new underruns and overruns are reported.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The counts of the underruns and the overruns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XrunCounts {
+    pub underruns: u64,
+    pub overruns: u64
+}
+
+impl XrunCounts {
+    pub fn is_empty(&self) -> bool {
+        self.underruns == 0 && self.overruns == 0
+    }
+}
+
+/// Monitors the frames sent to the audio carousel.
+#[derive(Debug, Clone)]
+pub struct CarouselMonitor {
+    frame_duration: Duration,
+    // the duration of all the carousel buffers
+    capacity: Duration,
+    // the estimated duration of the frames not played yet
+    buffered: Duration,
+    last_sent: Option<Instant>,
+    // the sending waits for the audio thread by design
+    paced: bool,
+    total: XrunCounts,
+    // the counts since the last report
+    pending: XrunCounts,
+    last_report: Option<Instant>
+}
+
+impl CarouselMonitor {
+    /// Creates the monitor of the carousel with `latency` buffers of the frames
+    /// lasting `frame_duration` each.
+    pub fn new(frame_duration: Duration, latency: usize) -> Self {
+        let latency = u32::try_from(latency.max(1)).unwrap_or(u32::MAX);
+        CarouselMonitor {
+            frame_duration,
+            capacity: frame_duration.saturating_mul(latency),
+            buffered: Duration::ZERO,
+            last_sent: None,
+            paced: false,
+            total: XrunCounts::default(),
+            pending: XrunCounts::default(),
+            last_report: None
+        }
+    }
+
+    /// Forgets the time of the last sent frame, e.g. when the audio is paused.
+    pub fn reset(&mut self) {
+        self.last_sent = None;
+    }
+
+    /// Sets whether the audio paces the frames, so the waiting isn't counted as an overrun.
+    pub fn set_paced(&mut self, paced: bool) {
+        self.paced = paced;
+    }
+
+    /// Counts the frame which sending started at `start` and took `wait`.
+    pub fn frame_sent(&mut self, start: Instant, wait: Duration) {
+        match self.last_sent {
+            Some(last_sent) => {
+                let elapsed = start.saturating_duration_since(last_sent);
+                match self.buffered.checked_sub(elapsed) {
+                    Some(buffered) => self.buffered = buffered,
+                    None => {
+                        self.buffered = Duration::ZERO;
+                        self.count(XrunCounts { underruns: 1, overruns: 0 });
+                    }
+                }
+            }
+            // the buffers are full when the carousel is created or paused
+            None => self.buffered = self.capacity
+        }
+        if wait > OVERRUN_WAIT {
+            self.buffered = self.capacity;
+            if !self.paced {
+                self.count(XrunCounts { underruns: 0, overruns: 1 });
+            }
+        }
+        else {
+            self.buffered = (self.buffered + self.frame_duration).min(self.capacity);
+        }
+        self.last_sent = Some(start + wait);
+    }
+
+    /// Returns all the counts so far.
+    pub fn total(&self) -> XrunCounts {
+        self.total
+    }
+
+    /// Returns the counts since the last report, if there are any and the last report
+    /// was made at least [REPORT_INTERVAL] before `now`.
+    pub fn report(&mut self, now: Instant) -> Option<XrunCounts> {
+        if self.pending.is_empty() {
+            return None
+        }
+        match self.last_report {
+            Some(last_report) if now.saturating_duration_since(last_report) < REPORT_INTERVAL => None,
+            _ => {
+                self.last_report = Some(now);
+                Some(core::mem::take(&mut self.pending))
+            }
+        }
+    }
+
+    fn count(&mut self, counts: XrunCounts) {
+        for xruns in [&mut self.total, &mut self.pending] {
+            xruns.underruns += counts.underruns;
+            xruns.overruns += counts.overruns;
+        }
+    }
+}
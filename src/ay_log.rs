@@ -0,0 +1,256 @@
+//! The log of the AY-3-891x sound register writes with the PSG and YM exporters.
+//!
+//! The writes are logged frame by frame. The PSG file keeps every write,
+//! while the YM file keeps the state of the registers at the end of each frame.
+use core::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::path::Path;
+
+/// The number of the sound generator registers.
+pub const AY_SOUND_REGISTERS: usize = 14;
+/// The number of the registers in each YM frame.
+pub const YM_FRAME_REGISTERS: usize = 16;
+
+const ENV_SHAPE_REGISTER: usize = 13;
+// the YM value of the envelope shape register when the envelope is not restarted
+const YM_ENV_SHAPE_UNCHANGED: u8 = 0xFF;
+// the meaningful bits of each register, the YM formats use the others for the effects
+const REGISTER_MASKS: [u8; AY_SOUND_REGISTERS] = [
+    0xFF, 0x0F, 0xFF, 0x0F, 0xFF, 0x0F, 0x1F, 0x3F, 0x1F, 0x1F, 0x1F, 0xFF, 0xFF, 0x0F
+];
+
+const PSG_END_OF_FRAME: u8 = 0xFF;
+const PSG_SKIP_FRAMES: u8 = 0xFE;
+const PSG_END_OF_MUSIC: u8 = 0xFD;
+
+/// The version of the YM format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum YmVersion {
+    Ym5,
+    #[default]
+    Ym6
+}
+
+/// The song information stored in the YM file header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YmInfo {
+    pub version: YmVersion,
+    /// The AY chip clock in Hz.
+    pub clock: u32,
+    /// The number of frames per second.
+    pub frame_rate: u16,
+    pub title: String,
+    pub author: String,
+    pub comment: String
+}
+
+/// The logged AY register writes.
+#[derive(Debug, Clone, Default)]
+pub struct AyLog {
+    // the register and value pairs of each frame
+    frames: Vec<Vec<(u8, u8)>>
+}
+
+impl AyLog {
+    pub fn new() -> Self {
+        AyLog::default()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Adds the next frame with the register writes in the order they were made.
+    ///
+    /// The writes to the I/O port registers are ignored.
+    pub fn push_frame<I: IntoIterator<Item=(u8, u8)>>(&mut self, writes: I) {
+        self.frames.push(writes.into_iter()
+                               .filter(|&(reg, _)| (reg as usize) < AY_SOUND_REGISTERS)
+                               .collect());
+    }
+
+    /// Writes the log in the PSG format.
+    pub fn write_psg<W: Write>(&self, mut wr: W) -> io::Result<()> {
+        wr.write_all(b"PSG\x1A")?;
+        wr.write_all(&[0; 12])?;
+        // the number of the frames ended but not written yet
+        let mut pending = 0;
+        for writes in self.frames.iter() {
+            if !writes.is_empty() {
+                write_psg_frame_ends(&mut wr, pending)?;
+                pending = 0;
+                for &(reg, val) in writes.iter() {
+                    wr.write_all(&[reg, val])?;
+                }
+            }
+            pending += 1;
+        }
+        write_psg_frame_ends(&mut wr, pending)?;
+        wr.write_all(&[PSG_END_OF_MUSIC])
+    }
+
+    /// Writes the log in the uncompressed and interleaved YM format.
+    ///
+    /// The registers that were not written since the logging started are assumed to be 0.
+    pub fn write_ym<W: Write>(&self, mut wr: W, info: &YmInfo) -> io::Result<()> {
+        let frame_count = u32::try_from(self.frames.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "too many frames")
+        })?;
+        wr.write_all(match info.version {
+            YmVersion::Ym5 => b"YM5!",
+            YmVersion::Ym6 => b"YM6!"
+        })?;
+        wr.write_all(b"LeOnArD!")?;
+        wr.write_all(&frame_count.to_be_bytes())?;
+        // the song attributes: interleaved
+        wr.write_all(&1u32.to_be_bytes())?;
+        // the number of digidrums
+        wr.write_all(&0u16.to_be_bytes())?;
+        wr.write_all(&info.clock.to_be_bytes())?;
+        wr.write_all(&info.frame_rate.to_be_bytes())?;
+        // the loop frame
+        wr.write_all(&0u32.to_be_bytes())?;
+        // the size of the additional data
+        wr.write_all(&0u16.to_be_bytes())?;
+        for text in [&info.title, &info.author, &info.comment] {
+            wr.write_all(text.as_bytes())?;
+            wr.write_all(&[0])?;
+        }
+        let states = self.ym_frames();
+        for reg in 0..YM_FRAME_REGISTERS {
+            let data: Vec<u8> = states.iter().map(|regs| regs[reg]).collect();
+            wr.write_all(&data)?;
+        }
+        wr.write_all(b"End!")
+    }
+
+    /// Saves the log as a PSG file.
+    pub fn save_psg<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_psg(&mut file)?;
+        file.flush()
+    }
+
+    /// Saves the log as a YM file.
+    pub fn save_ym<P: AsRef<Path>>(&self, path: P, info: &YmInfo) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_ym(&mut file, info)?;
+        file.flush()
+    }
+
+    // the state of the registers at the end of each frame
+    fn ym_frames(&self) -> Vec<[u8; YM_FRAME_REGISTERS]> {
+        let mut regs = [0u8; YM_FRAME_REGISTERS];
+        self.frames.iter().map(|writes| {
+            regs[ENV_SHAPE_REGISTER] = YM_ENV_SHAPE_UNCHANGED;
+            for &(reg, val) in writes.iter() {
+                let reg = reg as usize;
+                regs[reg] = val & REGISTER_MASKS[reg];
+            }
+            regs
+        }).collect()
+    }
+}
+
+// writes the end of `count` frames
+fn write_psg_frame_ends<W: Write>(wr: &mut W, mut count: usize) -> io::Result<()> {
+    // the skip command counts the frames by 4
+    while count >= 8 {
+        let quads = (count / 4).min(u8::MAX as usize);
+        wr.write_all(&[PSG_SKIP_FRAMES, quads as u8])?;
+        count -= quads * 4;
+    }
+    for _ in 0..count {
+        wr.write_all(&[PSG_END_OF_FRAME])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_log() -> AyLog {
+        let mut log = AyLog::new();
+        // the I/O port register write is ignored
+        log.push_frame(vec![(0, 0xFE), (1, 0xF1), (7, 0x38), (14, 0xAA), (13, 0x0E)]);
+        for _ in 0..10 {
+            log.push_frame(None);
+        }
+        log.push_frame(vec![(8, 0x0F), (8, 0x10)]);
+        log
+    }
+
+    #[test]
+    fn psg_is_written() {
+        let mut data = Vec::new();
+        test_log().write_psg(&mut data).unwrap();
+        assert_eq!(&data[..16], b"PSG\x1A\0\0\0\0\0\0\0\0\0\0\0\0");
+        assert_eq!(&data[16..], &[
+            0, 0xFE, 1, 0xF1, 7, 0x38, 13, 0x0E,
+            // 11 frames ended: 2 skips by 4 and 3 ends
+            PSG_SKIP_FRAMES, 2, PSG_END_OF_FRAME, PSG_END_OF_FRAME, PSG_END_OF_FRAME,
+            8, 0x0F, 8, 0x10,
+            PSG_END_OF_FRAME, PSG_END_OF_MUSIC
+        ][..]);
+    }
+
+    #[test]
+    fn empty_psg_is_written() {
+        let mut data = Vec::new();
+        AyLog::new().write_psg(&mut data).unwrap();
+        assert_eq!(data.len(), 17);
+        assert_eq!(data[16], PSG_END_OF_MUSIC);
+    }
+
+    #[test]
+    fn long_silence_is_skipped() {
+        let mut log = AyLog::new();
+        for _ in 0..1030 {
+            log.push_frame(None);
+        }
+        let mut data = Vec::new();
+        log.write_psg(&mut data).unwrap();
+        assert_eq!(&data[16..], &[
+            PSG_SKIP_FRAMES, 255, PSG_SKIP_FRAMES, 2,
+            PSG_END_OF_FRAME, PSG_END_OF_FRAME, PSG_END_OF_MUSIC
+        ][..]);
+    }
+
+    #[test]
+    fn ym_is_written() {
+        let info = YmInfo {
+            version: YmVersion::Ym5,
+            clock: 1_773_400,
+            frame_rate: 50,
+            title: "Title".into(),
+            author: "Author".into(),
+            comment: String::new()
+        };
+        let mut data = Vec::new();
+        test_log().write_ym(&mut data, &info).unwrap();
+        assert_eq!(&data[..12], b"YM5!LeOnArD!");
+        assert_eq!(&data[12..16], &12u32.to_be_bytes());
+        assert_eq!(&data[16..20], &1u32.to_be_bytes());
+        assert_eq!(&data[20..22], &[0, 0]);
+        assert_eq!(&data[22..26], &1_773_400u32.to_be_bytes());
+        assert_eq!(&data[26..28], &50u16.to_be_bytes());
+        assert_eq!(&data[28..34], &[0; 6]);
+        assert_eq!(&data[34..48], b"Title\0Author\0\0");
+        let regs = &data[48..data.len() - 4];
+        assert_eq!(&data[data.len() - 4..], b"End!");
+        assert_eq!(regs.len(), 12 * YM_FRAME_REGISTERS);
+        let reg = |n: usize| &regs[n * 12..(n + 1) * 12];
+        assert_eq!(reg(0), &[0xFE; 12]);
+        // the bits unused by the AY are masked
+        assert_eq!(reg(1), &[0x01; 12]);
+        assert_eq!(reg(7), &[0x38; 12]);
+        // the last write of the frame counts
+        assert_eq!(reg(8), &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10]);
+        // the envelope shape is only restarted in the frame it was written in
+        assert_eq!(reg(13), &[0x0E, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(reg(14), &[0; 12]);
+        assert_eq!(reg(15), &[0; 12]);
+    }
+}
@@ -0,0 +1,35 @@
+/*
+    This program is free to use under the terms of the Blue Oak Model License 1.0.0.
+    See: https://blueoakcouncil.org/license/1.0.0
+*/
+//! Builds a TAP file with a BASIC loader and CODE blocks from a directory of binaries
+//! or from a manifest file.
+//!
+//! See the [spectrusty_tutorial::tap_builder] module for the source format.
+use std::fs::File;
+use std::io::BufWriter;
+use spectrusty_tutorial::tap_builder::TapProject;
+
+type Result<T> = core::result::Result<T, Box<dyn std::error::Error>>;
+
+fn show_help() -> Result<()> {
+    eprintln!("{}: SOURCE_DIR|MANIFEST OUTPUT_TAP",
+            std::env::args().next().as_deref().unwrap_or("mktap"));
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (source, output) = match (args.next(), args.next()) {
+        (Some(source), Some(output)) => (source, output),
+        _ => return show_help()
+    };
+    let project = TapProject::open(&source)?;
+    let file = File::create(&output)?;
+    project.write_tap(BufWriter::new(file))?;
+    eprintln!("{}: loader \"{}\" with autostart at {}", output, project.name, project.autostart);
+    for block in &project.blocks {
+        eprintln!("    CODE \"{}\" {},{}", block.name, block.address, block.data.len());
+    }
+    Ok(())
+}
@@ -9,18 +9,31 @@
 use core::convert::TryFrom;
 use core::fmt::Write;
 use core::mem;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs::{File, OpenOptions};
-use std::io::{self, Read};
-use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions, Menu, MENU_KEY_SHIFT, MENU_KEY_ALT};
+use std::io::{self, Read, BufWriter};
+use std::time::{Duration, Instant};
+use cpal::{SampleFormat, SampleRate, traits::{DeviceTrait, HostTrait}};
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions, Menu, MENU_KEY_SHIFT, MENU_KEY_CTRL, MENU_KEY_ALT};
 use rand::prelude::*;
 #[allow(unused_imports)]
 use log::{error, warn, info, debug, trace};
-use spectrusty_tutorial::{*, menus::AppMenu};
+use spectrusty_tutorial::{*, menus::AppMenu, archive::*, tapefile::*, pzx::PzxWriter,
+                          pulse_stream::{PulseStream, PulseFormat},
+                          capture::*, palette::*, settings::Settings,
+                          filters::{CrtFilters, CrtFilter, FrameBlender},
+                          scaler::{WindowScale, AspectResampler, PAL_PIXEL_ASPECT},
+                          ulaplus::{UlaPlusBusDevice, render_ulaplus_frame},
+                          osd::{Osd, OsdLogger, ROM_FONT_ADDRESS, FONT_SIZE},
+                          script::{Script, Command, TapeCommand},
+                          ay_log::{AyLog, YmInfo, YmVersion},
+                          mixer::{Mixer, AyStereo, Source, BLEP_CHANNELS, CENTER_CHANNEL},
+                          audio_monitor::CarouselMonitor, pacing::AudioPacer,
+                          turbosound::TurboSoundBusDevice, save_monitor::SaveMonitor};
 
 use spectrusty::audio::{
     AudioSample, EarMicAmps4, EarOutAmps4, EarInAmps2,
-    Blep, BlepStereo, FromSample, UlaAudioFrame,
+    Blep, FromSample, UlaAudioFrame,
     synth::BandLimited,
     carousel::AudioFrameResult,
     host::cpal::AudioHandleAnyFormat
@@ -33,7 +46,7 @@ use spectrusty::bus::{
         MultiJoystickBusDevice, JoystickSelect,
         JoystickInterface
     },
-    ay::serial128::Ay3_8912Keypad
+    ay::{Ay3_891xMelodik, Ay3_891xFullerBox, AyAudioBusDevice, serial128::Ay3_8912Keypad}
 };
 use spectrusty::chip::{
     ControlUnit, HostConfig, MemoryAccess,
@@ -44,7 +57,7 @@ use spectrusty::chip::{
 };
 use spectrusty::memory::{ZxMemory, Memory16k, Memory48k};
 use spectrusty::video::{
-    Video, Palette, PixelBuffer, BorderSize, BorderColor,
+    Video, VideoFrame, Palette, PixelBuffer, BorderSize, BorderColor,
     pixel::{PixelBufP32, SpectrumPalA8R8G8B8}
 };
 use spectrusty::peripherals::{
@@ -76,7 +89,17 @@ struct ZxSpectrum<C: Cpu, U> {
 #[derive(Default)]
 struct EmulatorState {
     // the TAPE recorder, maybe a tape is inside?
-    tape: Tape<File>,
+    tape: Tape<TapeFile>,
+    // is the inserted tape from a ZIP archive?
+    archive: Option<ArchivedTape>,
+    // are we recording each saved program into a separate TAP file?
+    split_tape: Option<TapSplitter>,
+    // are we recording the raw MIC OUT pulses?
+    pulse_recorder: Option<PzxWriter<File>>,
+    // detects the MIC OUT pulses the TAP decoder couldn't make sense of
+    save_monitor: SaveMonitor,
+    // the EAR IN pulses from a named pipe or the standard input
+    pulse_stream: Option<PulseStream>,
     // a record of a previous frame EAR IN counter
     prev_ear_in_counter: u32,
     // is the emulation paused?
@@ -88,7 +111,56 @@ struct EmulatorState {
     // do we want to hear the tape signal?
     audible_tape: bool,
     // sub joystick index of the selected joystick device
-    sub_joy: usize
+    sub_joy: usize,
+    // are we logging the AY register writes?
+    ay_log: Option<AyLogCapture>,
+    // the AY interface plugged into the bus
+    ay_interface: Option<AyInterface>,
+    // is the second AY chip of the TurboSound enabled on the 128k?
+    turbosound: bool
+}
+
+// the AY sound interfaces for the 16k and 48k models
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AyInterface {
+    Melodik,
+    FullerBox,
+    // decoded the same way as the Melodik, on the ports of the 128k AY
+    ZxAy
+}
+
+impl AyInterface {
+    const ALL: [AyInterface; 3] = [
+        AyInterface::Melodik,
+        AyInterface::FullerBox,
+        AyInterface::ZxAy
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            AyInterface::Melodik => "Melodik",
+            AyInterface::FullerBox => "Fuller Box",
+            AyInterface::ZxAy => "ZX-AY"
+        }
+    }
+
+    // is the interface decoded on the ports of the built-in AY of the 128k?
+    fn uses_128k_ports(self) -> bool {
+        matches!(self, AyInterface::Melodik|AyInterface::ZxAy)
+    }
+}
+
+// the log of the AY register writes saved as the PSG and YM files
+struct AyLogCapture {
+    log: AyLog,
+    info: YmInfo
+}
+
+// the origin of the TAPE file opened from a ZIP archive
+#[derive(Clone)]
+struct ArchivedTape {
+    path: PathBuf,
+    entry: String
 }
 
 // our terminator for the device chain
@@ -97,8 +169,8 @@ type TerminatorDevice = NullDevice<FTs>;
 type OptionalBusDevice<D> = spectrusty::bus::OptionalBusDevice<D, TerminatorDevice>;
 
 type SerialKeypad128 = SerialKeypad<FTs>;
-// define Ula128 with a static mandatory device
-type Ula128AyKeypad<D=TerminatorDevice> = Ula128<Ay3_8912Keypad<D>>;
+// define Ula128 with the static mandatory devices: the TurboSound in front of the built-in AY
+type Ula128AyKeypad<D=TerminatorDevice> = Ula128<TurboSoundBusDevice<Ay3_8912Keypad<D>>>;
 
 type ZxSpectrum16k<C, D> = ZxSpectrum<C, UlaPAL<Memory16k, D>>;
 type ZxSpectrum48k<C, D> = ZxSpectrum<C, UlaPAL<Memory48k, D>>;
@@ -113,9 +185,21 @@ enum ZxSpectrumModel<C: Cpu, D: BusDevice=TerminatorDevice> {
 #[derive(Debug, Clone, Copy)]
 enum Action {
     ChangeModel(ModelReq),
+    ChangeBorder(BorderSize),
+    ChangeWindow(WindowMode),
     Exit
 }
 
+// how the frame is presented in the window
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct WindowMode {
+    scale: WindowScale,
+    // stretch the frame to the PAL pixel aspect ratio
+    pal_aspect: bool,
+    // a borderless window fit to the screen
+    fullscreen: bool
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ModelReq {
     Spectrum16,
@@ -123,32 +207,135 @@ enum ModelReq {
     Spectrum128,
 }
 
-// the type of the audio handle
-type Audio = AudioHandleAnyFormat;
+// the audio output, silent if there is no audio device
+enum Audio {
+    Device {
+        handle: AudioHandleAnyFormat,
+        monitor: CarouselMonitor,
+        // present if the audio paces the frames instead of the timer
+        pacer: Option<AudioPacer>
+    },
+    Null { sample_rate: u32 }
+}
+
+// the audio output options from the command line or the settings
+#[derive(Debug, Default)]
+struct AudioConfig {
+    // the number of the carousel buffers
+    latency: Option<usize>,
+    // the preferred sample rate, also the rate of the audio in the headless mode
+    sample_rate: Option<u32>,
+    // the preferred sample format
+    sample_format: Option<SampleFormat>,
+    // the name of the output device, otherwise the default device is used
+    device: Option<String>
+}
 // the type of the Blep implementation amplitude delta
 type BlepDelta = f32; // i16
-// the type of the Blep implementation
-type BandLim = BlepStereo<BandLimited<BlepDelta>>;
-// the audio carousel latency
+// the type of the Blep implementation, with the separate channels of each audio source
+type BandLim = BandLimited<BlepDelta>;
+// the default audio carousel latency
 const AUDIO_LATENCY: usize = 2;
+// the sample rate of the silent audio output and of the discarded audio in the headless mode
+const NULL_SAMPLE_RATE: u32 = 44100;
 
 struct Env<'a> {
     window: &'a mut Window,
     width: usize,
     height: usize,
     border: BorderSize,
+    window_mode: WindowMode,
+    resampler: &'a mut Option<AspectResampler>,
     pixels: &'a mut Vec<u32>,
     audio: &'a mut Audio,
-    blep: &'a mut BandLim
+    blep: &'a mut BandLim,
+    mixer: &'a mut Mixer,
+    av_capture: &'a mut Option<AvCapture>,
+    audio_capture: &'a mut Option<AudioCapture>,
+    gif_capture: &'a mut GifCapture,
+    palettes: &'a mut Palettes,
+    filters: &'a mut CrtFilters,
+    blender: &'a mut Option<FrameBlender>,
+    osd: &'a mut Osd,
+    settings: &'a mut Settings
+}
+
+// the frontend without the window and the audio device
+#[derive(Default)]
+struct Headless {
+    // the commands driving the emulation
+    script: Option<Script>,
+    // the number of frames to run, otherwise the emulation runs until the script ends
+    max_frames: Option<u64>,
+    // the number of the emulated frames
+    frames: u64,
+    // the keys held down by the script
+    keys_down: Vec<Key>,
+    // the sample rate of the rendered audio
+    sample_rate: Option<u32>,
+    // the audio recording
+    audio_capture: Option<AudioCapture>
+}
+
+struct HeadlessEnv<'a> {
+    width: usize,
+    height: usize,
+    border: BorderSize,
+    pixels: &'a mut Vec<u32>,
+    blep: &'a mut BandLim,
+    mixer: &'a Mixer,
+    palettes: &'a Palettes,
+    headless: &'a mut Headless
+}
+
+// the recording of the emulated audio output
+struct AudioCapture {
+    wav: WavWriter,
+    samples: Vec<i16>
+}
+
+// the animated GIF clips
+struct GifCapture {
+    // how many of the most recent frames are kept for the replay
+    replay_frames: usize,
+    // the most recent frames
+    replay: Option<GifClip>,
+    // the frames since the GIF recording started
+    recording: Option<GifClip>,
+    // the colours of the palette indices
+    palette: Colors
+}
+
+// the video and audio recording
+struct AvCapture {
+    video: Y4mWriter<BufWriter<File>>,
+    audio: WavWriter,
+    frame_rate: (u32, u32),
+    sample_rate: u32,
+    samples: Vec<i16>,
+    // the fractional part of the number of silent samples
+    silence_frac: f64
 }
 
 // the type of PixelBuffer
 type PixelBuf<'a> = PixelBufP32<'a>;
 // the type of PixelBuffer::Pixel
 type Pixel<'a> = <PixelBuf<'a> as PixelBuffer<'a>>::Pixel;
-// the palette used
+// the palette of the default colours
 type SpectrumPal = SpectrumPalA8R8G8B8;
 
+// the frame is rendered as palette indices, so the colours of the selected palette
+// can be applied afterwards
+struct IndexPal;
+
+impl Palette for IndexPal {
+    type Pixel = u32;
+
+    fn get_pixel(index: usize) -> Self::Pixel {
+        index as u32
+    }
+}
+
 // add ROMS to the binary resources
 static ROM48: &[u8]    = include_bytes!("../../resources/roms/48.rom");
 static ROM128_0: &[u8] = include_bytes!("../../resources/roms/128-0.rom");
@@ -183,8 +370,14 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
     where U: UlaCommon,
           Self: JoystickAccess
 {
-    fn info(&mut self) -> Result<String> {
-        let mut info = format!("ZX Spectrum {}k", self.ula.memory_ref().ram_ref().len() / 1024);
+    fn model_name(&self) -> String {
+        format!("ZX Spectrum {}k", self.ula.memory_ref().ram_ref().len() / 1024)
+    }
+
+    fn info(&mut self) -> Result<String>
+        where U: DeviceAccess
+    {
+        let mut info = self.model_name();
         if self.state.paused {
             info.push_str(" ⏸ ");
         }
@@ -197,33 +390,113 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
                 write!(info, " #{}", self.state.sub_joy + 1)?;
             }
         }
+        if let Some(interface) = self.state.ay_interface {
+            write!(info, " 🎵 {}", interface.name())?;
+        }
+        if self.ula.is_turbosound() {
+            info.push_str(" 🎵 TurboSound");
+        }
         // is the TAPE running?
         let running = self.state.tape.running;
+        let chunk_info = self.tape_chunk_info()?;
         // is there any TAPE inserted at all?
-        if let Some(tap) = self.state.tape.tap.as_mut() {
+        if let Some(tap) = self.state.tape.tap.as_ref() {
             let flash = if self.state.flash_tape { '⚡' } else { ' ' };
             // we'll show if the TAP sound is audible
             let audible = if self.state.audible_tape { '🔊' } else { '🔈' };
             match tap {
                 Tap::Reader(..) if running => write!(info, " 🖭{}{} ⏵", flash, audible)?,
                 Tap::Writer(..) if running => write!(info, " 🖭{}{} ⏺", flash, audible)?,
-                tap => {
-                    // The TAPE is paused so we'll show some TAP block metadata.
-                    let mut rd = tap.try_reader_mut()?;
-                    // `rd` when dropped will restore underlying file cursor position,
-                    // so it's perfectly save to use it to read the metadata of
-                    // the current chunk.
-                    let chunk_no = rd.rewind_chunk()?;
-                    let chunk_info = TapChunkInfo::try_from(rd.get_mut())?;
-                    // restore cursor position
-                    rd.done()?;
-                    write!(info, " 🖭{}{} {}: {}", flash, audible, chunk_no, chunk_info)?;
-                }
+                _ => write!(info, " 🖭{}{} {}", flash, audible, chunk_info.unwrap_or_default())?
             }
         }
         Ok(info)
     }
 
+    // the status lines of the OSD, the ROM font has no emoji
+    fn osd_status(&mut self) -> Result<Vec<String>>
+        where U: DeviceAccess
+    {
+        let mut lines = Vec::new();
+        let mut line = self.model_name();
+        if self.state.paused {
+            line.push_str(" PAUSED");
+        }
+        else if self.state.turbo {
+            line.push_str(" TURBO");
+        }
+        lines.push(line);
+        if let Some(joy_name) = self.current_joystick() {
+            let mut line = format!("Joystick: {}", joy_name);
+            if self.state.sub_joy != 0 {
+                write!(line, " #{}", self.state.sub_joy + 1)?;
+            }
+            lines.push(line);
+        }
+        if let Some(interface) = self.state.ay_interface {
+            lines.push(format!("AY interface: {}", interface.name()));
+        }
+        if self.ula.is_turbosound() {
+            lines.push("TurboSound".into());
+        }
+        let running = self.state.tape.running;
+        let chunk_info = self.tape_chunk_info()?;
+        if let Some(tap) = self.state.tape.tap.as_ref() {
+            let mut line = String::from("Tape:");
+            match tap {
+                Tap::Reader(..) if running => line.push_str(" PLAY"),
+                Tap::Writer(..) if running => line.push_str(" REC"),
+                _ => write!(line, " {}", chunk_info.unwrap_or_default())?
+            }
+            if self.state.flash_tape {
+                line.push_str(" [flash]");
+            }
+            if self.state.audible_tape {
+                line.push_str(" [audible]");
+            }
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+
+    // the current TAP chunk number and its metadata, if the TAPE is inserted and paused
+    fn tape_chunk_info(&mut self) -> Result<Option<String>> {
+        if self.state.tape.running {
+            return Ok(None)
+        }
+        if let Some(tap) = self.state.tape.tap.as_mut() {
+            let mut rd = tap.try_reader_mut()?;
+            // `rd` when dropped will restore underlying file cursor position,
+            // so it's perfectly save to use it to read the metadata of
+            // the current chunk.
+            let chunk_no = rd.rewind_chunk()?;
+            let chunk_info = TapChunkInfo::try_from(rd.get_mut())?;
+            // restore cursor position
+            rd.done()?;
+            return Ok(Some(format!("{}: {}", chunk_no, chunk_info)))
+        }
+        Ok(None)
+    }
+
+    // show the status either on the OSD or in the window title
+    fn update_status(&mut self, window: &mut Window, osd: &mut Osd, mixer: &Mixer) -> Result<()>
+        where U: DeviceAccess
+    {
+        if osd.is_enabled() {
+            let mut lines = self.osd_status()?;
+            lines.push(mixer.to_string());
+            osd.set_status(lines);
+            window.set_title(WINDOW_TITLE);
+        }
+        else {
+            let mut info = self.info()?;
+            let speaker = if mixer.is_muted() { '🔇' } else { '🔉' };
+            write!(info, " {} {}", speaker, mixer)?;
+            window.set_title(&info);
+        }
+        Ok(())
+    }
+
     fn update_keyboard<F: FnOnce(ZXKeyboardMap) -> ZXKeyboardMap>(
             &mut self,
             update_keys: F)
@@ -244,22 +517,50 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
         }
     }
 
+    // the key goes to the joystick or otherwise to the keyboard and the 128k keypad
+    fn update_from_key_event(&mut self, KeyEvent { key, pressed, shift_down, ctrl_down }: KeyEvent)
+        where U: DeviceAccess
+    {
+        if !update_joystick_from_key_event(key, pressed, FIRE_KEY,
+                                            || self.joystick_interface()) {
+            self.update_keyboard(|keymap|
+                update_keymap(keymap, key, pressed, shift_down, ctrl_down)
+            );
+            self.update_keypad128_keys(|padmap|
+                update_keypad_keys(padmap, key, pressed, shift_down || ctrl_down)
+            );
+        }
+    }
+
     // returns `Ok(is_recording)`
     fn record_tape_from_mic_out(&mut self) -> Result<bool> {
+        let raw_recording = self.record_raw_pulses_from_mic_out();
         // get the writer if the tape is inserted and is being recorded
         if let Some(ref mut writer) = self.state.tape.recording_writer_mut() {
+            let mut pulse_count = 0u32;
             // extract the MIC OUT state changes as a pulse iterator
-            let pulses_iter = self.ula.mic_out_pulse_iter();
+            let pulses_iter = self.ula.mic_out_pulse_iter()
+                                      .inspect(|_| pulse_count += 1);
             // decode the pulses as TAPE data and write it as a TAP chunk fragment
             match writer.write_pulses_as_tap_chunks(pulses_iter) {
                 Ok(chunks) => {
                     if chunks != 0 {
                         info!("Saved: {} TAP chunks", chunks);
+                        if let Some(splitter) = self.state.split_tape.as_mut() {
+                            match splitter.split_chunks() {
+                                Ok(paths) => for path in paths {
+                                    info!("Recording into: {}", path.display());
+                                }
+                                Err(err) => error!("Couldn't write the split TAP file: {:?}", err)
+                            }
+                        }
                     }
+                    // is the state of the pulse decoder idle?
+                    let idle = writer.get_ref().is_idle();
                     if self.state.turbo || self.state.flash_tape  {
-                        // is the state of the pulse decoder idle?
-                        self.state.turbo = !writer.get_ref().is_idle();
+                        self.state.turbo = !idle;
                     }
+                    self.state.detect_undecoded_pulses(chunks, pulse_count, idle);
                 }
                 Err(err) => {
                     error!("Couldn't write data to the TAP file: {:?}", err);
@@ -268,7 +569,20 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
             }
             return Ok(true)
         }
-        Ok(false)
+        Ok(raw_recording)
+    }
+
+    // returns `true` if recording the raw MIC OUT pulses
+    fn record_raw_pulses_from_mic_out(&mut self) -> bool {
+        if let Some(recorder) = self.state.pulse_recorder.as_mut() {
+            // the exact pulse lengths in T-states, whatever the encoding
+            if let Err(err) = recorder.write_pulses(self.ula.mic_out_pulse_iter()) {
+                error!("Couldn't write data to the PZX file: {:?}", err);
+                self.state.pulse_recorder = None;
+            }
+            return true
+        }
+        false
     }
 
     // very simple heuristics for detecting if spectrum needs some TAPE data
@@ -323,7 +637,22 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
         Ok(false)
     }
 
-    fn run_frame(&mut self) -> Result<(FTs, bool)> {
+    // feed EAR IN line with the pulses from the stream as they arrive
+    fn feed_ear_in_from_stream(&mut self) {
+        if let Some(stream) = self.state.pulse_stream.as_mut() {
+            if stream.has_pulses() {
+                self.ula.feed_ear_in(stream.pulses(), Some(1));
+            }
+            else if stream.is_ended() {
+                info!("End of the pulse stream");
+                self.state.pulse_stream = None;
+            }
+        }
+    }
+
+    fn run_frame(&mut self) -> Result<(FTs, bool)>
+        where U: DeviceAccess
+    {
         // for tracking an effective change
         let (turbo, running) = (self.state.turbo, self.state.tape.running);
 
@@ -341,6 +670,10 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
             // only report it when the tape was running before
             info!("Auto STOP: End of TAPE");
         }
+        // the playing tape takes precedence over the pulse stream
+        if !self.state.tape.is_playing() {
+            self.feed_ear_in_from_stream();
+        }
 
         if self.nmi_request && self.ula.nmi(&mut self.cpu) {
             // clear nmi_request only if the triggering succeeded
@@ -350,6 +683,9 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
             self.ula.reset(&mut self.cpu, hard);
         }
         self.ula.execute_next_frame(&mut self.cpu);
+        if let Some(capture) = self.state.ay_log.as_mut() {
+            capture.log.push_frame(self.ula.ay_reg_writes());
+        }
 
         let fts_delta = self.ula.current_tstate() - fts_start;
         let state_changed = running != self.state.tape.running ||
@@ -363,6 +699,7 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
             &mut self,
             time_sync: &mut ThreadSyncTimer
         ) -> Result<(FTs, bool)>
+        where U: DeviceAccess
     {
         let mut sum: FTs = 0;
         let mut state_changed = false;
@@ -390,20 +727,34 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
         self.ula.render_video_frame::<PixelBuf, P>(buffer, pitch, border);
     }
     // adds pulse steps to the `blep` and returns the number of samples ready to be produced.
-    fn render_audio<B: Blep<SampleDelta=BlepDelta>>(&mut self, blep: &mut B) -> usize
-        where U: UlaAudioFrame<B>
+    // `ay_channels` are the BLEP channels of the AY channels A, B and C.
+    // The beeper and the tape are rendered into the centre channels of their sources.
+    fn render_audio<B: Blep<SampleDelta=BlepDelta>>(&mut self, blep: &mut B, ay_channels: [usize; 3]) -> usize
+        where U: UlaAudioFrame<B> + DeviceAccess
     {
-        self.ula.render_ay_audio_frame::<AyAmps<BlepDelta>>(blep, [0, 1, 2]);
+        self.ula.render_ay_audio_frame::<AyAmps<BlepDelta>>(blep, ay_channels);
+        // the pluggable AY interfaces are rendered the same way as the built-in AY
+        let end_ts = self.ula.current_tstate();
+        let frame_tstates = <U as Video>::VideoFrame::FRAME_TSTATES_COUNT;
+        if let Some(ay_bus_dev) = self.ula.ay_bus_device_mut() {
+            if let Some(melodik) = ay_bus_dev.as_deref_mut() {
+                melodik.render_ay_audio::<AyAmps<BlepDelta>, _>(blep, end_ts, frame_tstates, ay_channels);
+            }
+            if let Some(fuller_box) = ay_bus_dev.next_device_mut().as_deref_mut() {
+                fuller_box.render_ay_audio::<AyAmps<BlepDelta>, _>(blep, end_ts, frame_tstates, ay_channels);
+            }
+        }
         // (1) add some amplitude steps to the BLEP that correspond to the EAR/MIC line changes
+        let beeper_channel = Source::Beeper.channel(CENTER_CHANNEL);
         if self.state.audible_tape {
             // render both EAR/MIC OUT channel
-            self.ula.render_earmic_out_audio_frame::<EarMicAmps4<BlepDelta>>(blep, 2);
+            self.ula.render_earmic_out_audio_frame::<EarMicAmps4<BlepDelta>>(blep, beeper_channel);
             // and the EAR IN channel
-            self.ula.render_ear_in_audio_frame::<EarInAmps2<BlepDelta>>(blep, 2);
+            self.ula.render_ear_in_audio_frame::<EarInAmps2<BlepDelta>>(blep, Source::Tape.channel(CENTER_CHANNEL));
         }
         else {
             // render only EAR OUT channel
-            self.ula.render_earmic_out_audio_frame::<EarOutAmps4<BlepDelta>>(blep, 2);
+            self.ula.render_earmic_out_audio_frame::<EarOutAmps4<BlepDelta>>(blep, beeper_channel);
         }
         // (2) finalize the BLEP frame
         self.ula.end_audio_frame(blep)
@@ -417,8 +768,15 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
         self.nmi_request = true;
     }
 
-    // insert a tape file by file path
+    // insert a tape file by file path, the path may point to a ZIP archive
+    // optionally followed by `#ENTRY` to select the TAP file inside
     fn insert_tape<P: AsRef<Path>>(&mut self, file_path: P) -> Result<()> {
+        if let Some((archive_path, entry)) = file_path.as_ref().to_str()
+                                                      .map(split_archive_path)
+                                                      .filter(|(path, _)| is_archive_path(path))
+        {
+            return self.insert_archived_tape(Path::new(archive_path), entry)
+        }
         info!("Inserting TAP file: {}", file_path.as_ref().display());
         // open the .tap file for reading and writing, allow creating a new file
         let tap_file = OpenOptions::new()
@@ -430,15 +788,103 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
             OpenOptions::new().read(true).open(file_path)
         })?;
         // wrap the file into the TapChunkPulseIter
-        let iter_pulse = read_tap_pulse_iter(tap_file);
+        let iter_pulse = read_tap_pulse_iter(TapeFile::from(tap_file));
         self.state.tape.tap.replace(Tap::Reader(iter_pulse));
         // or instead we could just write:
         // self.tape.insert_as_reader(tap_file);
+        self.state.archive = None;
+        self.state.split_tape = None;
+        self.state.audible_tape = true;
+        self.state.flash_tape = true;
+        Ok(())
+    }
+
+    // insert a tape from the ZIP archive, select the first TAP file if `entry` is `None`
+    fn insert_archived_tape(&mut self, archive_path: &Path, entry: Option<&str>) -> Result<()> {
+        let (mut archive, entries) = open_archive(archive_path)?;
+        if entries.len() > 1 {
+            info!("TAP files in the archive: {}", entries.join(", "));
+        }
+        let name = select_entry(&entries, entry)?;
+        info!("Inserting TAP file: {} from the archive: {}", name, archive_path.display());
+        // the archived file is decompressed into memory and can be only read
+        let tap_file = read_archive_entry(&mut archive, name)?;
+        let iter_pulse = read_tap_pulse_iter(tap_file);
+        self.state.tape.tap.replace(Tap::Reader(iter_pulse));
+        self.state.archive = Some(ArchivedTape {
+            path: archive_path.to_path_buf(),
+            entry: name.to_string()
+        });
+        self.state.split_tape = None;
         self.state.audible_tape = true;
         self.state.flash_tape = true;
         Ok(())
     }
 
+    // insert the next TAP file from the same ZIP archive
+    fn next_archived_tape(&mut self) -> Result<()> {
+        let archived = match self.state.archive.clone() {
+            Some(archived) => archived,
+            None => return Ok(())
+        };
+        let (_, entries) = open_archive(&archived.path)?;
+        let next = entries.iter().position(|e| *e == archived.entry)
+                                 .and_then(|index| entries.get((index + 1) % entries.len()));
+        self.insert_archived_tape(&archived.path, next.map(String::as_str))
+    }
+
+    // record each saved program into a new TAP file in the `dir` directory,
+    // a new file is started each time a header block is saved
+    fn record_tape_into_dir<P: Into<PathBuf>>(&mut self, dir: P) -> Result<()> {
+        let (splitter, tap_file) = TapSplitter::new(dir);
+        info!("Recording TAP files into: {}", splitter.dir().display());
+        // the chunks are recorded into memory first and then split into files
+        self.state.tape.tap.replace(Tap::Reader(read_tap_pulse_iter(tap_file)));
+        self.state.tape.record()?;
+        self.state.archive = None;
+        self.state.split_tape = Some(splitter);
+        self.state.flash_tape = true;
+        Ok(())
+    }
+
+    // play the pulses from the file, the named pipe or "-" for the standard input
+    fn open_pulse_stream<P: AsRef<Path>>(&mut self, path: P, format: PulseFormat) {
+        info!("Reading {:?} pulses from: {}", format, path.as_ref().display());
+        self.state.pulse_stream = Some(PulseStream::open(path, format));
+    }
+
+    // start or stop recording the raw MIC OUT pulses to a PZX file
+    fn toggle_raw_recording(&mut self) -> Result<()> {
+        if let Some(recorder) = self.state.pulse_recorder.take() {
+            recorder.finish()?;
+            info!("Raw pulse recording stopped");
+        }
+        else if let Some(file_path) = save_pzx_dialog() {
+            self.record_raw_pulses(file_path)?;
+        }
+        Ok(())
+    }
+
+    // record the raw MIC OUT pulses into a new PZX file
+    fn record_raw_pulses<P: AsRef<Path>>(&mut self, file_path: P) -> Result<()> {
+        info!("Recording raw pulses to: {}", file_path.as_ref().display());
+        let file = File::create(file_path)?;
+        let recorder = PzxWriter::new(file, "Recorded with SPECTRUSTY")?;
+        self.state.pulse_recorder = Some(recorder);
+        Ok(())
+    }
+
+    // start recording, but refuse to record into the archived tape
+    fn record_tape(&mut self) -> Result<()> {
+        if let Some(ArchivedTape { path, entry }) = self.state.archive.as_ref() {
+            error!("Can't record into the TAP file: {} inside the ZIP archive: {}, \
+                    create a new TAPE file instead", entry, path.display());
+            return Ok(())
+        }
+        self.state.tape.record()?;
+        Ok(())
+    }
+
     // open the file dialog and insert a selected tape file
     fn open_tape(&mut self) {
         if let Some(file_path) = open_tape_dialog() {
@@ -457,7 +903,127 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
         }
     }
 
-    fn update_on_user_request(&mut self, menu_id: usize) -> Result<Option<Action>> {
+    // open the directory dialog and record the split TAP files into it
+    fn save_tape_dir(&mut self) {
+        if let Some(dir) = save_tape_dir_dialog() {
+            if let Err(err) = self.record_tape_into_dir(&dir) {
+                error!("Error recording TAP files into: {} {}", dir.display(), err);
+            }
+        }
+    }
+
+    // plug in or remove the ULAplus device
+    fn toggle_ulaplus(&mut self)
+        where U: DeviceAccess
+    {
+        if let Some(ulaplus_bus_dev) = self.ula.ulaplus_bus_device_mut() {
+            if ulaplus_bus_dev.is_some() {
+                **ulaplus_bus_dev = None;
+                info!("ULAplus removed");
+            }
+            else {
+                **ulaplus_bus_dev = Some(UlaPlusDevice::default());
+                info!("ULAplus plugged in");
+            }
+        }
+    }
+
+    // plug in the AY interface or remove it if it's already plugged in
+    fn select_ay_interface(&mut self, interface: Option<AyInterface>)
+        where U: DeviceAccess
+    {
+        let interface = interface.filter(|&iface| self.state.ay_interface != Some(iface));
+        if let Some(iface) = interface.filter(|iface| iface.uses_128k_ports()) {
+            if self.ula.has_builtin_ay() {
+                warn!("AY interface: {} can't be heard, its ports belong to the built-in AY", iface.name());
+                return
+            }
+        }
+        if let Some(ay_bus_dev) = self.ula.ay_bus_device_mut() {
+            **ay_bus_dev = match interface {
+                Some(AyInterface::Melodik)|Some(AyInterface::ZxAy) => Some(Default::default()),
+                _ => None
+            };
+            **ay_bus_dev.next_device_mut() = match interface {
+                Some(AyInterface::FullerBox) => Some(Default::default()),
+                _ => None
+            };
+            self.state.ay_interface = interface;
+            match interface {
+                Some(iface) => info!("AY interface: {} plugged in", iface.name()),
+                None => info!("AY interface removed")
+            }
+            if self.state.ay_log.is_some() {
+                self.warn_unlogged_ay_chips();
+            }
+        }
+    }
+
+    // the PSG and YM files have room for a single chip, on the 128k only the built-in AY is logged
+    fn warn_unlogged_ay_chips(&self)
+        where U: DeviceAccess
+    {
+        if !self.ula.has_builtin_ay() {
+            return
+        }
+        if self.ula.is_turbosound() {
+            warn!("Only the first AY chip of the TurboSound is logged");
+        }
+        if let Some(iface) = self.state.ay_interface {
+            warn!("Only the built-in AY is logged, the AY interface: {} is not", iface.name());
+        }
+    }
+
+    // enable or disable the second AY chip of the TurboSound
+    fn toggle_turbosound(&mut self)
+        where U: DeviceAccess
+    {
+        self.enable_turbosound(!self.ula.is_turbosound());
+    }
+
+    fn enable_turbosound(&mut self, enabled: bool)
+        where U: DeviceAccess
+    {
+        if self.ula.set_turbosound(enabled) {
+            self.state.turbosound = enabled;
+            if enabled {
+                info!("TurboSound enabled");
+                if self.state.ay_log.is_some() {
+                    self.warn_unlogged_ay_chips();
+                }
+            }
+            else {
+                info!("TurboSound disabled");
+            }
+        }
+        else {
+            warn!("TurboSound is available for the 128k model only");
+        }
+    }
+
+    // render the frame with the ULAplus palette if the palette mode is enabled,
+    // `pixels` should contain the palette indices, returns `false` if not rendered
+    fn render_ulaplus(&self, pixels: &mut [u32], width: usize, height: usize) -> bool
+        where U: DeviceAccess + ScreenAccess
+    {
+        let colors = match self.ula.ulaplus_bus_device_ref()
+                                   .and_then(|ulaplus_bus_dev| (**ulaplus_bus_dev).as_ref())
+                                   .filter(|ulaplus| ulaplus.is_palette_mode()) {
+            Some(ulaplus) => ulaplus.colors(),
+            None => return false
+        };
+        match self.ula.memory_ref().screen_ref(self.ula.screen_bank()) {
+            Ok(screen) => {
+                render_ulaplus_frame(pixels, width, height, &screen[..], &colors);
+                true
+            }
+            Err(_) => false
+        }
+    }
+
+    fn update_on_user_request(&mut self, menu_id: usize) -> Result<Option<Action>>
+        where U: DeviceAccess
+    {
         match menu_id {
             MENU_EXIT_ID         => return Ok(Some(Action::Exit)),
             MENU_MODEL_16_ID     => return Ok(Some(Action::ChangeModel(ModelReq::Spectrum16))),
@@ -472,7 +1038,7 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
             MENU_PAUSE_ID        => { self.state.paused = true; }
             MENU_TAPE_REWIND_ID  => { self.state.tape.rewind_nth_chunk(1)?; }
             MENU_TAPE_PLAY_ID    => { self.state.tape.play()?; }
-            MENU_TAPE_RECORD_ID  => { self.state.tape.record()?; }
+            MENU_TAPE_RECORD_ID  => { self.record_tape()?; }
             MENU_TAPE_STOP_ID    => { self.state.tape.stop(); }
             MENU_TAPE_PREV_ID    => { self.state.tape.rewind_prev_chunk()?; }
             MENU_TAPE_NEXT_ID    => { self.state.tape.forward_chunk()?; }
@@ -480,13 +1046,66 @@ impl<C: Cpu, U> ZxSpectrum<C, U>
             MENU_TAPE_FLASH_ID   => { self.state.flash_tape = !self.state.flash_tape; }
             MENU_TAPE_OPEN_ID    => { self.open_tape(); }
             MENU_TAPE_SAVE_ID    => { self.save_tape(); }
-            MENU_TAPE_EJECT_ID   => { self.state.tape.eject(); }
+            MENU_TAPE_SPLIT_ID   => { self.save_tape_dir(); }
+            MENU_TAPE_RAW_ID     => { self.toggle_raw_recording()?; }
+            MENU_TAPE_EJECT_ID   => {
+                self.state.tape.eject();
+                self.state.archive = None;
+                self.state.split_tape = None;
+            }
+            MENU_TAPE_ARCHIVE_NEXT_ID => { self.next_archived_tape()?; }
+            MENU_ULAPLUS_ID      => { self.toggle_ulaplus(); }
+            MENU_AY_INTERFACE_ID..=MENU_AY_INTERFACE_LAST_ID|
+            MENU_AY_INTERFACE_NONE_ID => {
+                self.select_ay_interface(AyInterface::ALL.get(menu_id - MENU_AY_INTERFACE_ID).copied());
+            }
             _ => {}
         }
         Ok(None)
     }
 }
 
+impl EmulatorState {
+    // start logging the AY register writes or stop and save the log
+    fn toggle_ay_log(&mut self, info: YmInfo) -> Result<()> {
+        if self.ay_log.is_some() {
+            self.finish_ay_log()
+        }
+        else {
+            info!("AY logging started");
+            self.ay_log = Some(AyLogCapture { log: AyLog::new(), info });
+            Ok(())
+        }
+    }
+
+    // save the AY log as the time stamped PSG and YM files in the current directory
+    fn finish_ay_log(&mut self) -> Result<()> {
+        if let Some(AyLogCapture { log, info }) = self.ay_log.take() {
+            let path = timestamped_path(".", "ay", "psg");
+            let ym_path = path.with_extension("ym");
+            log.save_psg(&path)?;
+            log.save_ym(&ym_path, &info)?;
+            info!("AY log of {} frames saved: {} and {}",
+                  log.frame_count(), path.display(), ym_path.display());
+        }
+        Ok(())
+    }
+
+    // warns if the MIC OUT pulses are being saved, but no TAP chunk comes out of them
+    fn detect_undecoded_pulses(&mut self, chunks: usize, pulse_count: u32, idle: bool) {
+        if let Some(undecoded) = self.save_monitor.frame(chunks, pulse_count, idle) {
+            if self.pulse_recorder.is_some() {
+                warn!("The TAP decoder failed on {} MIC OUT pulses, a non-standard save? \
+                       The pulses were recorded raw to the PZX file.", undecoded);
+            }
+            else {
+                warn!("The TAP decoder failed on {} MIC OUT pulses, a non-standard save? \
+                       Try the raw pulse recording instead.", undecoded);
+            }
+        }
+    }
+}
+
 trait DeviceAccess {
     type JoystickDevice;
 
@@ -496,9 +1115,42 @@ trait DeviceAccess {
     fn joystick_bus_device_ref(&self) -> Option<&Self::JoystickDevice> {
         None
     }
+    fn ulaplus_bus_device_mut(&mut self) -> Option<&mut PluggableUlaPlusBusDevice> {
+        None
+    }
+    fn ulaplus_bus_device_ref(&self) -> Option<&PluggableUlaPlusBusDevice> {
+        None
+    }
+    fn ay_bus_device_mut(&mut self) -> Option<&mut PluggableAyBusDevice> {
+        None
+    }
+    fn ay_bus_device_ref(&self) -> Option<&PluggableAyBusDevice> {
+        None
+    }
     fn keypad128_mut(&mut self) -> Option<&mut SerialKeypad128> {
         None
     }
+    fn has_builtin_ay(&self) -> bool {
+        false
+    }
+    fn is_turbosound(&self) -> bool {
+        false
+    }
+    // enable or disable the TurboSound, returns `false` if it's not available
+    fn set_turbosound(&mut self, _enabled: bool) -> bool {
+        false
+    }
+    // the AY sound registers and the values written during the last frame
+    fn ay_reg_writes(&self) -> Vec<(u8, u8)> {
+        Vec::new()
+    }
+}
+
+trait ScreenAccess {
+    // the index of the screen memory bank being displayed
+    fn screen_bank(&self) -> usize {
+        0
+    }
 }
 
 trait JoystickAccess {
@@ -516,26 +1168,66 @@ trait JoystickAccess {
 
 // a pluggable joystick with run-time selectable joystick types
 type PluggableMultiJoyBusDevice = OptionalBusDevice<MultiJoystickBusDevice<TerminatorDevice>>;
+// a pluggable Fuller Box AY interface followed by the pluggable joystick
+type PluggableFullerBoxBusDevice = spectrusty::bus::OptionalBusDevice<Ay3_891xFullerBox<TerminatorDevice>,
+                                                                      PluggableMultiJoyBusDevice>;
+// a pluggable AY interface on the 128k ports: the Melodik or the ZX-AY, followed by the Fuller Box
+type PluggableAyBusDevice = spectrusty::bus::OptionalBusDevice<Ay3_891xMelodik<TerminatorDevice>,
+                                                               PluggableFullerBoxBusDevice>;
+// the ULAplus device
+type UlaPlusDevice = UlaPlusBusDevice<TerminatorDevice>;
+// a pluggable ULAplus device followed by the pluggable AY interfaces
+type PluggableUlaPlusBusDevice = spectrusty::bus::OptionalBusDevice<UlaPlusDevice,
+                                                                    PluggableAyBusDevice>;
+
+// the 16k and 48k models always display the same screen
+impl<M: ZxMemory, D: BusDevice> ScreenAccess for UlaPAL<M, D> {}
+
+impl<D: BusDevice> ScreenAccess for Ula128AyKeypad<D> {
+    fn screen_bank(&self) -> usize {
+        self.ula128_mem_port_value().contains(Ula128MemFlags::SCREEN_BANK) as usize
+    }
+}
 
 // implement for Ula with a default device for completness
 impl<M: ZxMemory> DeviceAccess for UlaPAL<M> {
     type JoystickDevice = PluggableMultiJoyBusDevice;
 }
 
-// implement for Ula with a joystick device
-impl<M: ZxMemory> DeviceAccess for UlaPAL<M, PluggableMultiJoyBusDevice> {
+// implement for Ula with the ULAplus, the AY interfaces and a joystick device
+impl<M: ZxMemory> DeviceAccess for UlaPAL<M, PluggableUlaPlusBusDevice> {
     type JoystickDevice = PluggableMultiJoyBusDevice;
 
     fn joystick_bus_device_mut(
             &mut self
         ) -> Option<&mut Self::JoystickDevice>
     {
-        Some(self.bus_device_mut())
+        Some(self.bus_device_mut().next_device_mut().next_device_mut().next_device_mut())
     }
 
     fn joystick_bus_device_ref(&self) -> Option<&Self::JoystickDevice> {
+        Some(self.bus_device_ref().next_device_ref().next_device_ref().next_device_ref())
+    }
+
+    fn ulaplus_bus_device_mut(&mut self) -> Option<&mut PluggableUlaPlusBusDevice> {
+        Some(self.bus_device_mut())
+    }
+
+    fn ulaplus_bus_device_ref(&self) -> Option<&PluggableUlaPlusBusDevice> {
         Some(self.bus_device_ref())
     }
+
+    fn ay_bus_device_mut(&mut self) -> Option<&mut PluggableAyBusDevice> {
+        Some(self.bus_device_mut().next_device_mut())
+    }
+
+    fn ay_bus_device_ref(&self) -> Option<&PluggableAyBusDevice> {
+        Some(self.bus_device_ref().next_device_ref())
+    }
+
+    fn ay_reg_writes(&self) -> Vec<(u8, u8)> {
+        ay_interface_reg_writes(self.bus_device_ref().next_device_ref())
+    }
 }
 
 // implement for Ula128 with a default device for completness
@@ -543,36 +1235,105 @@ impl DeviceAccess for Ula128AyKeypad {
     type JoystickDevice = PluggableMultiJoyBusDevice;
 
     fn keypad128_mut(&mut self) -> Option<&mut SerialKeypad128> {
-        Some(&mut self.bus_device_mut().ay_io.port_a.serial1)
+        Some(&mut self.bus_device_mut().next_device_mut().ay_io.port_a.serial1)
+    }
+
+    fn has_builtin_ay(&self) -> bool {
+        true
+    }
+
+    fn is_turbosound(&self) -> bool {
+        self.bus_device_ref().is_enabled()
+    }
+
+    fn set_turbosound(&mut self, enabled: bool) -> bool {
+        self.bus_device_mut().set_enabled(enabled);
+        true
+    }
+
+    // only the built-in AY is logged, the PSG and YM files have no room for the second chip
+    fn ay_reg_writes(&self) -> Vec<(u8, u8)> {
+        self.bus_device_ref().next_device_ref().ay_io.recorder.0.iter()
+            .map(|&(_, reg, val)| (reg as u8, val))
+            .collect()
     }
 }
 
-// implement for Ula128 with a joystick device
-impl DeviceAccess for Ula128AyKeypad<PluggableMultiJoyBusDevice> {
+// implement for Ula128 with the ULAplus, the AY interfaces and a joystick device
+impl DeviceAccess for Ula128AyKeypad<PluggableUlaPlusBusDevice> {
     type JoystickDevice = PluggableMultiJoyBusDevice;
 
     fn joystick_bus_device_mut(
             &mut self
         ) -> Option<&mut Self::JoystickDevice>
     {
-        Some(self.bus_device_mut().next_device_mut())
+        Some(self.bus_device_mut().next_device_mut().next_device_mut().next_device_mut()
+                 .next_device_mut().next_device_mut())
     }
 
     fn joystick_bus_device_ref(&self) -> Option<&Self::JoystickDevice> {
-        Some(self.bus_device_ref().next_device_ref())
+        Some(self.bus_device_ref().next_device_ref().next_device_ref().next_device_ref()
+                 .next_device_ref().next_device_ref())
     }
 
-    fn keypad128_mut(&mut self) -> Option<&mut SerialKeypad128> {
-        Some(&mut self.bus_device_mut().ay_io.port_a.serial1)
+    fn ulaplus_bus_device_mut(&mut self) -> Option<&mut PluggableUlaPlusBusDevice> {
+        Some(self.bus_device_mut().next_device_mut().next_device_mut())
     }
-}
 
-impl<C: Cpu, U: UlaCommon> JoystickAccess for ZxSpectrum<C, U>
-    where U: DeviceAccess<JoystickDevice = PluggableMultiJoyBusDevice>
-{
-    type JoystickInterface = dyn JoystickInterface;
+    fn ulaplus_bus_device_ref(&self) -> Option<&PluggableUlaPlusBusDevice> {
+        Some(self.bus_device_ref().next_device_ref().next_device_ref())
+    }
 
-    fn joystick_interface(
+    // the Melodik and the ZX-AY would be shadowed by the built-in AY, but the Fuller Box can be heard
+    fn ay_bus_device_mut(&mut self) -> Option<&mut PluggableAyBusDevice> {
+        Some(self.bus_device_mut().next_device_mut().next_device_mut().next_device_mut())
+    }
+
+    fn ay_bus_device_ref(&self) -> Option<&PluggableAyBusDevice> {
+        Some(self.bus_device_ref().next_device_ref().next_device_ref().next_device_ref())
+    }
+
+    fn keypad128_mut(&mut self) -> Option<&mut SerialKeypad128> {
+        Some(&mut self.bus_device_mut().next_device_mut().ay_io.port_a.serial1)
+    }
+
+    fn has_builtin_ay(&self) -> bool {
+        true
+    }
+
+    fn is_turbosound(&self) -> bool {
+        self.bus_device_ref().is_enabled()
+    }
+
+    fn set_turbosound(&mut self, enabled: bool) -> bool {
+        self.bus_device_mut().set_enabled(enabled);
+        true
+    }
+
+    // only the built-in AY is logged, the PSG and YM files have no room for the second chip
+    fn ay_reg_writes(&self) -> Vec<(u8, u8)> {
+        self.bus_device_ref().next_device_ref().ay_io.recorder.0.iter()
+            .map(|&(_, reg, val)| (reg as u8, val))
+            .collect()
+    }
+}
+
+// the AY register writes of the plugged AY interface during the last frame
+fn ay_interface_reg_writes(ay_bus_dev: &PluggableAyBusDevice) -> Vec<(u8, u8)> {
+    let recorder = match (ay_bus_dev.as_deref(), ay_bus_dev.next_device_ref().as_deref()) {
+        (Some(melodik), _) => &melodik.ay_io.recorder,
+        (None, Some(fuller_box)) => &fuller_box.ay_io.recorder,
+        (None, None) => return Vec::new()
+    };
+    recorder.0.iter().map(|&(_, reg, val)| (reg as u8, val)).collect()
+}
+
+impl<C: Cpu, U: UlaCommon> JoystickAccess for ZxSpectrum<C, U>
+    where U: DeviceAccess<JoystickDevice = PluggableMultiJoyBusDevice>
+{
+    type JoystickInterface = dyn JoystickInterface;
+
+    fn joystick_interface(
             &mut self
         ) -> Option<&mut Self::JoystickInterface>
     {
@@ -638,7 +1399,11 @@ impl<C, D> From<ZxSpectrumModel<C, D>> for ZxSpectrum<C, Ula128AyKeypad<D>>
         spectrum.cpu = cpu;
         spectrum.state = state;
         spectrum.ula.set_border_color(border);
-        *spectrum.ula.bus_device_mut().next_device_mut() = dev;
+        *spectrum.ula.bus_device_mut().next_device_mut().next_device_mut() = dev;
+        spectrum.ula.bus_device_mut().set_enabled(spectrum.state.turbosound);
+        if let Some(iface) = spectrum.state.ay_interface.filter(|iface| iface.uses_128k_ports()) {
+            warn!("AY interface: {} can't be heard, its ports belong to the built-in AY", iface.name());
+        }
         // lock in 48k mode until reset
         spectrum.ula.set_ula128_mem_port_value(Ula128MemFlags::ROM_BANK
                                               |Ula128MemFlags::LOCK_MMU);
@@ -659,11 +1424,18 @@ impl<C: Cpu, D> ZxSpectrumModel<C, D>
             ),
             ZxSpectrumModel::Spectrum128(spec128) => (
                 spec128.cpu,
-                spec128.ula.into_bus_device().into_next_device(),
+                spec128.ula.into_bus_device().into_next_device().into_next_device(),
                 spec128.state
             ),
         }        
     }
+    fn state_mut(&mut self) -> &mut EmulatorState {
+        match self {
+            ZxSpectrumModel::Spectrum16(spec16) => &mut spec16.state,
+            ZxSpectrumModel::Spectrum48(spec48) => &mut spec48.state,
+            ZxSpectrumModel::Spectrum128(spec128) => &mut spec128.state
+        }
+    }
     // returns a dynamically dispatched reader from RAM
     fn read_ram<'a>(&'a self) -> Box<dyn Read + 'a> {
         match self {
@@ -712,9 +1484,45 @@ const MENU_SOFT_RESET_ID:   usize = 2;
 const MENU_TRIG_NMI_ID:     usize = 3;
 const MENU_PAUSE_ID:        usize = 6;
 const MENU_TURBO_ID:        usize = 7;
+const MENU_SCREENSHOT_ID:   usize = 8;
+const MENU_SCREENSHOT_SCREEN_ID: usize = 9;
 const MENU_MODEL_16_ID:     usize = 10;
 const MENU_MODEL_48_ID:     usize = 11;
 const MENU_MODEL_128_ID:    usize = 12;
+const MENU_RECORD_AV_ID:    usize = 20;
+const MENU_GIF_REPLAY_ID:   usize = 21;
+const MENU_GIF_RECORD_ID:   usize = 22;
+const MENU_RECORD_AUDIO_ID: usize = 23;
+const MENU_AY_LOG_ID:       usize = 24;
+const MENU_YM_VERSION_ID:   usize = 25;
+const MENU_BORDER_ID:       usize = 30;
+const MENU_PALETTE_ID:      usize = 40;
+const MENU_PALETTE_LAST_ID: usize = MENU_PALETTE_ID + BUILTIN_NAMES.len() - 1;
+const MENU_PALETTE_NEXT_ID: usize = 48;
+const MENU_PALETTE_LOAD_ID: usize = 49;
+const MENU_FILTER_ID:       usize = 50;
+const MENU_FILTER_LAST_ID:  usize = MENU_FILTER_ID + CrtFilter::ALL.len() - 1;
+const MENU_SCALE_ID:        usize = 60;
+const MENU_SCALE_LAST_ID:   usize = MENU_SCALE_ID + WindowScale::ALL.len() - 1;
+const MENU_SCALE_NEXT_ID:   usize = 64;
+const MENU_PAL_ASPECT_ID:   usize = 65;
+const MENU_FULLSCREEN_ID:   usize = 66;
+const MENU_ULAPLUS_ID:      usize = 70;
+const MENU_BLEND_ID:        usize = 71;
+const MENU_OSD_ID:          usize = 72;
+const MENU_AY_STEREO_ID:    usize = 80;
+const MENU_AY_STEREO_LAST_ID: usize = MENU_AY_STEREO_ID + AyStereo::ALL.len() - 1;
+const MENU_AY_STEREO_NEXT_ID: usize = 84;
+const MENU_SEPARATION_DEC_ID: usize = 85;
+const MENU_SEPARATION_INC_ID: usize = 86;
+const MENU_MUTE_ID:         usize = 87;
+const MENU_VOLUME_DEC_ID:   usize = 88;
+const MENU_VOLUME_INC_ID:   usize = 89;
+// each source has the items decreasing and increasing its level
+const MENU_LEVEL_ID:        usize = 90;
+const MENU_LEVEL_LAST_ID:   usize = MENU_LEVEL_ID + 2 * Source::ALL.len() - 1;
+const MENU_AUDIO_PACING_ID: usize = 96;
+const MENU_TURBOSOUND_ID:   usize = 97;
 const MENU_TAPE_REWIND_ID:  usize = 100;
 const MENU_TAPE_PLAY_ID:    usize = 101;
 const MENU_TAPE_RECORD_ID:  usize = 102;
@@ -726,16 +1534,31 @@ const MENU_TAPE_FLASH_ID:   usize = 107;
 const MENU_TAPE_OPEN_ID:    usize = 108;
 const MENU_TAPE_SAVE_ID:    usize = 109;
 const MENU_TAPE_EJECT_ID:   usize = 110;
+const MENU_TAPE_ARCHIVE_NEXT_ID: usize = 111;
+const MENU_TAPE_SPLIT_ID:   usize = 112;
+const MENU_TAPE_RAW_ID:     usize = 113;
 const MENU_JOY_KEMPSTON_ID: usize = 201;
 const MENU_JOY_FULLER_ID:   usize = 202;
 const MENU_JOY_IF2_0_ID:    usize = 203;
 const MENU_JOY_IF2_1_ID:    usize = 204;
 const MENU_JOY_AGF_ID:      usize = 205;
 const MENU_JOY_NONE_ID:     usize = 299;
+const MENU_AY_INTERFACE_ID: usize = 301;
+const MENU_AY_INTERFACE_LAST_ID: usize = MENU_AY_INTERFACE_ID + AyInterface::ALL.len() - 1;
+const MENU_AY_INTERFACE_NONE_ID: usize = 399;
 
-fn open_window(title: &str, width: usize, height: usize) -> Result<Window> {
+fn open_window(title: &str, width: usize, height: usize, mode: WindowMode) -> Result<Window> {
     let mut winopt = WindowOptions::default();
-    winopt.scale = Scale::X2;
+    // minifb can't switch to the fullscreen mode, so the borderless window is fit to the screen
+    winopt.scale = match mode.scale {
+        _ if mode.fullscreen => Scale::FitScreen,
+        WindowScale::X1 => Scale::X1,
+        WindowScale::X2 => Scale::X2,
+        WindowScale::X4 => Scale::X4,
+        WindowScale::Fit => Scale::FitScreen
+    };
+    winopt.borderless = mode.fullscreen;
+    winopt.topmost = mode.fullscreen;
     let mut window = Window::new(&title, width, height, winopt)
                             .map_err(|e| e.to_string())?;
     window.limit_update_rate(None);
@@ -768,6 +1591,30 @@ fn open_window(title: &str, width: usize, height: usize) -> Result<Window> {
     menu.add_item("Toggle Pause", MENU_PAUSE_ID)
         .shortcut(Key::Pause, 0)
         .build();
+    menu.add_item("Screenshot", MENU_SCREENSHOT_ID)
+        .shortcut(Key::F12, 0)
+        .build();
+    menu.add_item("Screenshot without border", MENU_SCREENSHOT_SCREEN_ID)
+        .shortcut(Key::F12, MENU_KEY_SHIFT)
+        .build();
+    menu.add_item("Start/Stop recording", MENU_RECORD_AV_ID)
+        .shortcut(Key::F11, 0)
+        .build();
+    menu.add_item("Start/Stop audio recording", MENU_RECORD_AUDIO_ID)
+        .shortcut(Key::F11, MENU_KEY_SHIFT)
+        .build();
+    menu.add_item("Start/Stop AY logging (PSG/YM)", MENU_AY_LOG_ID)
+        .shortcut(Key::F10, MENU_KEY_SHIFT)
+        .build();
+    menu.add_item("Toggle the YM5/YM6 log format", MENU_YM_VERSION_ID)
+        .shortcut(Key::F8, MENU_KEY_SHIFT)
+        .build();
+    menu.add_item("Save the last seconds as GIF", MENU_GIF_REPLAY_ID)
+        .shortcut(Key::F9, 0)
+        .build();
+    menu.add_item("Start/Stop GIF recording", MENU_GIF_RECORD_ID)
+        .shortcut(Key::F9, MENU_KEY_SHIFT)
+        .build();
     menu.add_sub_menu("Select model", &models);
     menu.add_item("Exit", MENU_EXIT_ID)
         .shortcut(Key::F10, 0)
@@ -780,6 +1627,15 @@ fn open_window(title: &str, width: usize, height: usize) -> Result<Window> {
     tape.add_item("Create a new TAPE file", MENU_TAPE_SAVE_ID)
         .shortcut(Key::Insert, MENU_KEY_ALT)
         .build();
+    tape.add_item("Record TAPE files into a directory", MENU_TAPE_SPLIT_ID)
+        .shortcut(Key::F7, MENU_KEY_ALT)
+        .build();
+    tape.add_item("Toggle raw pulse recording (PZX)", MENU_TAPE_RAW_ID)
+        .shortcut(Key::F7, MENU_KEY_SHIFT)
+        .build();
+    tape.add_item("Next TAPE file in the archive", MENU_TAPE_ARCHIVE_NEXT_ID)
+        .shortcut(Key::Insert, MENU_KEY_SHIFT)
+        .build();
     tape.add_item("Rewind TAPE", MENU_TAPE_REWIND_ID)
         .shortcut(Key::Home, 0)
         .build();
@@ -828,13 +1684,126 @@ fn open_window(title: &str, width: usize, height: usize) -> Result<Window> {
           .shortcut(Key::F5, MENU_KEY_ALT)
           .build();
 
+    let mut palettes = Menu::new("Palette").map_err(|e| e.to_string())?;
+    for (index, name) in BUILTIN_NAMES.iter().enumerate() {
+        palettes.add_item(name, MENU_PALETTE_ID + index).build();
+    }
+    palettes.add_item("Next palette", MENU_PALETTE_NEXT_ID)
+            .shortcut(Key::F2, MENU_KEY_CTRL)
+            .build();
+    palettes.add_item("Load a palette file", MENU_PALETTE_LOAD_ID)
+            .shortcut(Key::F2, MENU_KEY_CTRL|MENU_KEY_SHIFT)
+            .build();
+
+    let mut filters = Menu::new("CRT filters").map_err(|e| e.to_string())?;
+    for (index, (filter, key)) in CrtFilter::ALL.iter()
+                                  .zip([Key::F3, Key::F4, Key::F5, Key::F6])
+                                  .enumerate() {
+        filters.add_item(filter.name(), MENU_FILTER_ID + index)
+               .shortcut(key, MENU_KEY_CTRL)
+               .build();
+    }
+
+    let mut display = Menu::new("Display").map_err(|e| e.to_string())?;
+    display.add_item("Cycle border size", MENU_BORDER_ID)
+           .shortcut(Key::F1, MENU_KEY_CTRL)
+           .build();
+    display.add_sub_menu("Palette", &palettes);
+    display.add_sub_menu("CRT filters", &filters);
+
+    let mut scales = Menu::new("Window scale").map_err(|e| e.to_string())?;
+    for (index, scale) in WindowScale::ALL.iter().enumerate() {
+        scales.add_item(&scale.to_string(), MENU_SCALE_ID + index).build();
+    }
+    scales.add_item("Next scale", MENU_SCALE_NEXT_ID)
+          .shortcut(Key::F7, MENU_KEY_CTRL)
+          .build();
+    display.add_sub_menu("Window scale", &scales);
+    display.add_item("Toggle PAL aspect ratio", MENU_PAL_ASPECT_ID)
+           .shortcut(Key::F8, MENU_KEY_CTRL)
+           .build();
+    display.add_item("Toggle fullscreen", MENU_FULLSCREEN_ID)
+           .shortcut(Key::F11, MENU_KEY_CTRL)
+           .build();
+    display.add_item("Toggle ULAplus", MENU_ULAPLUS_ID)
+           .shortcut(Key::F12, MENU_KEY_CTRL)
+           .build();
+    display.add_item("Toggle frame blending", MENU_BLEND_ID)
+           .shortcut(Key::F9, MENU_KEY_CTRL)
+           .build();
+    display.add_item("Toggle on-screen display", MENU_OSD_ID)
+           .shortcut(Key::F10, MENU_KEY_CTRL)
+           .build();
+
+    let mut ay_stereo = Menu::new("AY stereo").map_err(|e| e.to_string())?;
+    for (index, stereo) in AyStereo::ALL.iter().enumerate() {
+        ay_stereo.add_item(&stereo.to_string(), MENU_AY_STEREO_ID + index).build();
+    }
+    ay_stereo.add_item("Next layout", MENU_AY_STEREO_NEXT_ID)
+             .shortcut(Key::F9, MENU_KEY_ALT)
+             .build();
+
+    let mut ay_interfaces = Menu::new("AY interface").map_err(|e| e.to_string())?;
+    ay_interfaces.add_item("None", MENU_AY_INTERFACE_NONE_ID)
+                 .shortcut(Key::F4, MENU_KEY_SHIFT)
+                 .build();
+    for (index, (interface, key)) in AyInterface::ALL.iter()
+                                     .zip([Key::F1, Key::F2, Key::F3])
+                                     .enumerate() {
+        ay_interfaces.add_item(interface.name(), MENU_AY_INTERFACE_ID + index)
+                     .shortcut(key, MENU_KEY_SHIFT)
+                     .build();
+    }
+
+    let mut sound = Menu::new("Audio").map_err(|e| e.to_string())?;
+    sound.add_sub_menu("AY interface", &ay_interfaces);
+    sound.add_item("Toggle TurboSound (128k)", MENU_TURBOSOUND_ID)
+         .shortcut(Key::F5, MENU_KEY_SHIFT)
+         .build();
+    sound.add_sub_menu("AY stereo", &ay_stereo);
+    sound.add_item("Decrease stereo separation", MENU_SEPARATION_DEC_ID)
+         .shortcut(Key::F10, MENU_KEY_ALT)
+         .build();
+    sound.add_item("Increase stereo separation", MENU_SEPARATION_INC_ID)
+         .shortcut(Key::F11, MENU_KEY_ALT)
+         .build();
+
+    let mut levels = Menu::new("Levels").map_err(|e| e.to_string())?;
+    let level_keys = [(Key::F4, Key::F5), (Key::F6, Key::F7), (Key::F8, Key::F9)];
+    for (index, (source, (dec_key, inc_key))) in Source::ALL.iter().zip(level_keys).enumerate() {
+        levels.add_item(&format!("Decrease {} level", source), MENU_LEVEL_ID + 2 * index)
+              .shortcut(dec_key, MENU_KEY_SHIFT|MENU_KEY_ALT)
+              .build();
+        levels.add_item(&format!("Increase {} level", source), MENU_LEVEL_ID + 2 * index + 1)
+              .shortcut(inc_key, MENU_KEY_SHIFT|MENU_KEY_ALT)
+              .build();
+    }
+
+    sound.add_item("Mute", MENU_MUTE_ID)
+         .shortcut(Key::F12, MENU_KEY_ALT)
+         .build();
+    sound.add_item("Decrease volume", MENU_VOLUME_DEC_ID)
+         .shortcut(Key::PageDown, MENU_KEY_ALT)
+         .build();
+    sound.add_item("Increase volume", MENU_VOLUME_INC_ID)
+         .shortcut(Key::PageUp, MENU_KEY_ALT)
+         .build();
+    sound.add_sub_menu("Levels", &levels);
+    sound.add_item("Toggle audio-driven frame pacing", MENU_AUDIO_PACING_ID)
+         .shortcut(Key::F6, MENU_KEY_SHIFT)
+         .build();
+
     window.add_menu(&menu);
     window.add_menu(&tape);
     window.add_menu(&sticks);
+    window.add_menu(&display);
+    window.add_menu(&sound);
 
     Ok(window)
 }
 
+const WINDOW_TITLE: &str = "ZX Spectrum";
+
 const FIRE_KEY: Key = Key::RightCtrl;
 
 struct KeyEvent {
@@ -857,6 +1826,188 @@ fn process_keyboard_window_events<F: FnMut(KeyEvent)>(window: &Window, mut updat
     handle_update(window.get_keys_released(), false);
 }
 
+// select a palette from the menu and remember the choice
+fn update_palette_on_user_request(menu_id: usize, palettes: &mut Palettes, settings: &mut Settings) {
+    match menu_id {
+        MENU_PALETTE_NEXT_ID => { palettes.select_next(); }
+        MENU_PALETTE_LOAD_ID => {
+            if let Some(file_path) = open_palette_dialog() {
+                match ColorPalette::from_file(&file_path) {
+                    Ok(palette) => {
+                        palettes.add(palette);
+                        settings.store("palette_file", file_path.display());
+                    }
+                    Err(err) => {
+                        error!("Error loading the palette: {} {}", file_path.display(), err);
+                        return
+                    }
+                }
+            }
+        }
+        menu_id => { palettes.select(menu_id - MENU_PALETTE_ID); }
+    }
+    info!("Palette: {}", palettes.current().name);
+    settings.store("palette", &palettes.current().name);
+}
+
+// change the AY stereo layout or the stereo separation and remember the choice
+fn update_mixer_on_user_request(menu_id: usize, mixer: &mut Mixer, settings: &mut Settings) {
+    match menu_id {
+        MENU_AY_STEREO_NEXT_ID => { mixer.ay_stereo = mixer.ay_stereo.next(); }
+        MENU_SEPARATION_DEC_ID => { mixer.adjust_separation(-1); }
+        MENU_SEPARATION_INC_ID => { mixer.adjust_separation(1); }
+        menu_id => { mixer.ay_stereo = AyStereo::ALL[menu_id - MENU_AY_STEREO_ID]; }
+    }
+    info!("AY stereo: {}, separation: {:.0}%", mixer.ay_stereo, mixer.separation() * 100.0);
+    settings.set("ay_stereo", mixer.ay_stereo);
+    settings.store("ay_separation", mixer.separation());
+}
+
+// mute the audio or change the master volume or the level of a source and remember the choice,
+// the mute is not remembered
+fn update_volume_on_user_request(menu_id: usize, mixer: &mut Mixer, settings: &mut Settings) {
+    match menu_id {
+        MENU_MUTE_ID => { mixer.toggle_mute(); }
+        MENU_VOLUME_DEC_ID => { mixer.adjust_volume(-1); }
+        MENU_VOLUME_INC_ID => { mixer.adjust_volume(1); }
+        menu_id => {
+            let index = menu_id - MENU_LEVEL_ID;
+            let steps = if index % 2 == 0 { -1 } else { 1 };
+            mixer.adjust_level(Source::ALL[index / 2], steps);
+        }
+    }
+    info!("Audio: {}", mixer);
+    for source in Source::ALL {
+        settings.set(level_setting_key(source), mixer.level(source));
+    }
+    settings.store("volume", mixer.volume());
+}
+
+// e.g. "ay_level"
+fn level_setting_key(source: Source) -> String {
+    format!("{}_level", source.name().to_lowercase())
+}
+
+// the mixer with the AY stereo layout, the stereo separation, the volume and the source levels
+// from the previous run
+fn setup_mixer(settings: &Settings) -> Mixer {
+    let mut mixer = Mixer::new();
+    if let Some(stereo) = settings.parse("ay_stereo") {
+        mixer.ay_stereo = stereo;
+    }
+    if let Some(separation) = settings.parse("ay_separation") {
+        mixer.set_separation(separation);
+    }
+    if let Some(volume) = settings.parse("volume") {
+        mixer.set_volume(volume);
+    }
+    for source in Source::ALL {
+        if let Some(level) = settings.parse(&level_setting_key(source)) {
+            mixer.set_level(source, level);
+        }
+    }
+    mixer
+}
+
+// initialize the palettes with the custom palette file and the palette selected
+// previously or by the user
+fn setup_palettes(settings: &Settings, palette: Option<String>) -> Result<Palettes> {
+    // the default colours are the ones of the SPECTRUSTY's palette
+    let mut colors = [0; PALETTE_SIZE];
+    for (index, color) in colors.iter_mut().enumerate() {
+        *color = SpectrumPal::get_pixel(index);
+    }
+    let mut palettes = Palettes::new(colors);
+    if let Some(file_path) = settings.get("palette_file") {
+        match ColorPalette::from_file(file_path) {
+            Ok(custom) => { palettes.add(custom); }
+            Err(err) => warn!("Couldn't load the palette: {} {}", file_path, err)
+        }
+    }
+    match palette.as_deref().or_else(|| settings.get("palette")) {
+        // the palette from the command line is either a name or a file path
+        Some(name) => if palettes.select_by_name(name).is_none() {
+            if palette.is_some() {
+                palettes.add(ColorPalette::from_file(name)?);
+            }
+            else {
+                warn!("Unknown palette: {}", name);
+                palettes.select(0);
+            }
+        }
+        None => { palettes.select(0); }
+    }
+    Ok(palettes)
+}
+
+// the window mode changed by the menu item
+fn update_window_mode(menu_id: usize, mode: WindowMode) -> WindowMode {
+    match menu_id {
+        MENU_SCALE_NEXT_ID => WindowMode { scale: mode.scale.next(), ..mode },
+        MENU_PAL_ASPECT_ID => WindowMode { pal_aspect: !mode.pal_aspect, ..mode },
+        MENU_FULLSCREEN_ID => WindowMode { fullscreen: !mode.fullscreen, ..mode },
+        menu_id => WindowMode { scale: WindowScale::ALL[menu_id - MENU_SCALE_ID], ..mode }
+    }
+}
+
+// open the window for the frame of `width` x `height` pixels, the frame stretched
+// to the PAL pixel aspect ratio needs the resampler
+fn open_display(
+        width: usize,
+        height: usize,
+        mode: WindowMode
+    ) -> Result<(Window, Option<AspectResampler>)>
+{
+    let resampler = if mode.pal_aspect {
+        Some(AspectResampler::new(width, PAL_PIXEL_ASPECT))
+    }
+    else {
+        None
+    };
+    let window_width = resampler.as_ref().map(|r| r.dst_width()).unwrap_or(width);
+    let window = open_window(WINDOW_TITLE, window_width, height, mode)?;
+    Ok((window, resampler))
+}
+
+// the border size that follows `border` in the order from the largest to none at all
+fn next_border_size(border: BorderSize) -> BorderSize {
+    use BorderSize::*;
+    match border {
+        Full    => Large,
+        Large   => Medium,
+        Medium  => Small,
+        Small   => Tiny,
+        Tiny    => Minimal,
+        Minimal => Nil,
+        Nil     => Full
+    }
+}
+
+// show the frame in the window, stretched to the PAL pixel aspect ratio if requested,
+// with the OSD drawn over it
+fn update_display(
+        window: &mut Window,
+        pixels: &[u32],
+        width: usize,
+        height: usize,
+        resampler: &mut Option<AspectResampler>,
+        osd: &mut Osd
+    ) -> Result<()>
+{
+    let (buffer, buffer_width) = match resampler.as_mut() {
+        Some(resampler) => {
+            let buffer_width = resampler.dst_width();
+            (resampler.resample(pixels), buffer_width)
+        }
+        None => (pixels, width)
+    };
+    // the OSD is drawn on a copy, so it won't get into the screenshots and recordings
+    let buffer = osd.overlay(buffer, buffer_width, height);
+    window.update_with_buffer(buffer, buffer_width, height)
+          .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
 // transform the frame buffer to the format needed by render_video
 fn acquire_video_buffer(pixels: &mut [u32], pixel_width: usize) -> (&mut [u8], usize) {
     let pitch = pixel_width * mem::size_of::<u32>();
@@ -868,55 +2019,481 @@ fn produce_audio_frame<T: AudioSample + FromSample<BlepDelta>>(
         output_channels: usize,
         outbuf: &mut Vec<T>,
         blep: &mut BandLim,
+        mixer: &Mixer
     )
 {
-    // the diff buffer summing iterator of the channel 0
-    let sample_iter = blep.sum_iter::<T>(0);
-    // the number of samples that the iterator will generate
-    let frame_sample_count = sample_iter.len();
+    // the diff buffer summing iterators of all the source channels
+    let mut sample_iters: Vec<_> = (0..BLEP_CHANNELS).map(|channel| blep.sum_iter::<BlepDelta>(channel))
+                                                     .collect();
+    // the number of samples that the iterators will generate
+    let frame_sample_count = sample_iters[0].len();
     // ensure the size of the audio frame buffer is exactly as we need it
     outbuf.resize(frame_sample_count * output_channels, T::silence());
-    // zip with the other channel
-    let sample_iter = sample_iter.zip(blep.sum_iter::<T>(1));
+    let gains = mixer.gains();
+    let mut samples = [0.0; BLEP_CHANNELS];
     // render each sample
-    for (chans, (lsmp, rsmp)) in outbuf.chunks_mut(output_channels).zip(sample_iter) {
+    for chans in outbuf.chunks_mut(output_channels) {
+        for (sample, sample_iter) in samples.iter_mut().zip(sample_iters.iter_mut()) {
+            *sample = sample_iter.next().unwrap_or_default();
+        }
+        // the sources are mixed before the conversion to the output format
+        let (lsmp, rsmp) = gains.mix(&samples);
         // write each sample to each channel
         for (ch, sample) in chans.iter_mut().zip(&[lsmp, rsmp]) {
-            *ch = *sample;
+            *ch = T::from_sample(*sample);
         }
     }
 }
 
 fn produce_and_send_audio_frame(
         audio: &mut AudioHandleAnyFormat,
-        blep: &mut BandLim
+        blep: &mut BandLim,
+        mixer: &Mixer
     ) -> AudioFrameResult<()>
 {
     let channels = audio.channels().into();
     match audio {
         AudioHandleAnyFormat::I16(audio) =>
-            audio.producer.render_frame(|out| produce_audio_frame(channels, out, blep)),
+            audio.producer.render_frame(|out| produce_audio_frame(channels, out, blep, mixer)),
         AudioHandleAnyFormat::U16(audio) =>
-            audio.producer.render_frame(|out| produce_audio_frame(channels, out, blep)),
+            audio.producer.render_frame(|out| produce_audio_frame(channels, out, blep, mixer)),
         AudioHandleAnyFormat::F32(audio) =>
-            audio.producer.render_frame(|out| produce_audio_frame(channels, out, blep)),
+            audio.producer.render_frame(|out| produce_audio_frame(channels, out, blep, mixer)),
     }
     // send the frame buffer to the consumer
     audio.send_frame()
 }
 
-#[cfg(feature = "measure_cpu_freq")]
-use spectrusty::video::VideoFrame;
+impl Audio {
+    // the audio of the configured output device, or the silent output if there is none
+    fn create_or_null(frame_duration_nanos: u32, config: &AudioConfig) -> Self {
+        let latency = config.latency.unwrap_or(AUDIO_LATENCY).max(1);
+        match create_audio_handle(frame_duration_nanos, latency, config) {
+            Ok(audio) => {
+                info!("Audio output: {} Hz, {:?}, {} channels, latency: {} frames",
+                      audio.sample_rate(), audio.sample_format(), audio.channels(), latency);
+                let frame_duration = Duration::from_nanos(frame_duration_nanos.into());
+                Audio::Device {
+                    handle: audio,
+                    monitor: CarouselMonitor::new(frame_duration, latency),
+                    pacer: None
+                }
+            }
+            Err(err) => {
+                warn!("No audio output: {}, the frames will be timed by the thread timer only", err);
+                Audio::Null { sample_rate: config.sample_rate.unwrap_or(NULL_SAMPLE_RATE) }
+            }
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            Audio::Device { handle, .. } => handle.sample_rate(),
+            Audio::Null { sample_rate } => *sample_rate
+        }
+    }
+
+    fn play(&mut self) -> Result<()> {
+        if let Audio::Device { handle, .. } = self {
+            handle.play()?;
+        }
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        if let Audio::Device { handle, monitor, pacer } = self {
+            handle.pause()?;
+            // no frames are sent while paused, that's not an underrun
+            monitor.reset();
+            if let Some(pacer) = pacer {
+                pacer.reset();
+            }
+        }
+        Ok(())
+    }
+
+    // the audio paces the frames if possible, returns `true` if it does
+    fn set_pacing(&mut self, enabled: bool) -> bool {
+        match self {
+            Audio::Device { monitor, pacer, .. } => {
+                *pacer = if enabled { Some(AudioPacer::new()) } else { None };
+                monitor.set_paced(enabled);
+                enabled
+            }
+            Audio::Null { .. } => false
+        }
+    }
+
+    fn is_pacing(&self) -> bool {
+        matches!(self, Audio::Device { pacer: Some(..), .. })
+    }
+
+    // the ratio of the emulated clock rate keeping the audio paced frames in real-time
+    fn pacing_ratio(&self) -> Option<f64> {
+        match self {
+            Audio::Device { pacer: Some(pacer), .. } => Some(pacer.ratio()),
+            _ => None
+        }
+    }
+
+    // the silent output drops the frame, the ThreadSyncTimer alone keeps the pace then,
+    // otherwise sending waits for the audio thread to take the frame
+    fn send_frame(&mut self, blep: &mut BandLim, mixer: &Mixer, frame_duration: Duration) -> Result<()> {
+        if let Audio::Device { handle, monitor, pacer } = self {
+            let start = Instant::now();
+            produce_and_send_audio_frame(handle, blep, mixer)?;
+            let now = Instant::now();
+            monitor.frame_sent(start, now - start);
+            if let Some(pacer) = pacer {
+                pacer.frame_sent(now, frame_duration);
+            }
+            if let Some(xruns) = monitor.report(now) {
+                warn!("Audio underruns: {}, overruns: {}, try changing the latency with -L",
+                      xruns.underruns, xruns.overruns);
+            }
+        }
+        Ok(())
+    }
+
+    // log the underruns and the overruns counted since the audio output was created
+    fn report_xruns(&self) {
+        if let Audio::Device { monitor, .. } = self {
+            let total = monitor.total();
+            info!("Audio underruns: {}, overruns: {}", total.underruns, total.overruns);
+        }
+    }
+}
+
+// the audio handle of the configured or the default output device, with the preferred
+// sample rate and format if the device supports them
+fn create_audio_handle(
+        frame_duration_nanos: u32,
+        latency: usize,
+        config: &AudioConfig
+    ) -> Result<AudioHandleAnyFormat>
+{
+    let host = cpal::default_host();
+    let device = match config.device.as_deref() {
+        Some(name) => {
+            let mut names = Vec::new();
+            let mut found = None;
+            for device in host.output_devices()? {
+                let device_name = device.name()?;
+                if device_name == name {
+                    found = Some(device);
+                    break
+                }
+                names.push(device_name);
+            }
+            found.ok_or_else(|| {
+                format!("no audio device named \"{}\", choose from: {}", name, names.join("|"))
+            })?
+        }
+        None => host.default_output_device().ok_or("no default audio output device")?
+    };
+    if config.sample_rate.is_none() && config.sample_format.is_none() {
+        return Ok(AudioHandleAnyFormat::create_with_device(&device, frame_duration_nanos, latency)?)
+    }
+    let default_config = device.default_output_config()?;
+    let sample_format = config.sample_format.unwrap_or_else(|| default_config.sample_format());
+    let sample_rate = config.sample_rate.map(SampleRate).unwrap_or_else(|| default_config.sample_rate());
+    // prefer the default number of channels
+    let supported = device.supported_output_configs()?
+        .filter(|range| range.sample_format() == sample_format &&
+                        range.min_sample_rate() <= sample_rate &&
+                        range.max_sample_rate() >= sample_rate)
+        .max_by_key(|range| range.channels() == default_config.channels());
+    match supported {
+        Some(range) => {
+            let stream_config = range.with_sample_rate(sample_rate).config();
+            Ok(AudioHandleAnyFormat::create_with_device_config_and_sample_format(
+                &device, &stream_config, sample_format, frame_duration_nanos, latency)?)
+        }
+        None => {
+            warn!("The audio device doesn't support {} Hz {:?}, using the default format instead",
+                  sample_rate.0, sample_format);
+            Ok(AudioHandleAnyFormat::create_with_device(&device, frame_duration_nanos, latency)?)
+        }
+    }
+}
+
+// the sample format by its name
+fn parse_sample_format(name: &str) -> Option<SampleFormat> {
+    match name.to_ascii_lowercase().as_str() {
+        "i16" => Some(SampleFormat::I16),
+        "u16" => Some(SampleFormat::U16),
+        "f32" => Some(SampleFormat::F32),
+        _ => None
+    }
+}
+
+// the audio output options from the settings, overridden by the command line options
+fn setup_audio_config(settings: &Settings, mut config: AudioConfig) -> AudioConfig {
+    if config.latency.is_none() {
+        config.latency = settings.parse("audio_latency");
+    }
+    if config.sample_rate.is_none() {
+        config.sample_rate = settings.parse("sample_rate");
+    }
+    if config.sample_format.is_none() {
+        config.sample_format = settings.get("sample_format").and_then(parse_sample_format);
+    }
+    if config.device.is_none() {
+        config.device = settings.get("audio_device").map(String::from);
+    }
+    config
+}
+
+// save the rendered frame as a time stamped PNG file in the current directory,
+// optionally only the 256x192 screen area without the border
+fn save_screenshot(pixels: &[u32], width: usize, height: usize, no_border: bool) {
+    let path = timestamped_path(".", "screenshot", "png");
+    save_screenshot_to(&path, pixels, width, height, no_border);
+}
+
+fn save_screenshot_to(path: &Path, pixels: &[u32], width: usize, height: usize, no_border: bool) {
+    let res = if no_border {
+        save_png(&path, &crop_border(pixels, width, height), SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+    else {
+        save_png(&path, pixels, width, height)
+    };
+    match res {
+        Ok(()) => info!("Screenshot saved: {}", path.display()),
+        Err(err) => error!("Couldn't save the screenshot: {} {}", path.display(), err)
+    }
+}
+
+impl AvCapture {
+    // start recording to the time stamped Y4M and WAV files in the current directory
+    fn start(width: usize, height: usize, frame_rate: (u32, u32), sample_rate: u32) -> Result<Self> {
+        let path = timestamped_path(".", "video", "y4m");
+        let wav_path = path.with_extension("wav");
+        let video = Y4mWriter::new(BufWriter::new(File::create(&path)?), width, height, frame_rate)?;
+        let audio = create_wav(&wav_path, sample_rate, 2)?;
+        info!("Recording video: {} and audio: {}", path.display(), wav_path.display());
+        Ok(AvCapture { video, audio, frame_rate, sample_rate, samples: Vec::new(), silence_frac: 0.0 })
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.video.flush()?;
+        finalize_wav(self.audio)?;
+        info!("Video recording stopped");
+        Ok(())
+    }
+
+    // record the frame together with the audio rendered by the BLEP
+    fn record_frame(&mut self, pixels: &[u32], blep: &mut BandLim, mixer: &Mixer) -> io::Result<()> {
+        self.video.write_frame(pixels)?;
+        produce_audio_frame(2, &mut self.samples, blep, mixer);
+        write_wav_samples(&mut self.audio, &self.samples)
+    }
+
+    // record the frame with a frame duration worth of silence
+    fn record_frame_silent(&mut self, pixels: &[u32]) -> io::Result<()> {
+        self.video.write_frame(pixels)?;
+        let (num, den) = self.frame_rate;
+        let count = self.sample_rate as f64 * den as f64 / num as f64 + self.silence_frac;
+        self.silence_frac = count.fract();
+        self.samples.clear();
+        self.samples.resize(count as usize * 2, 0);
+        write_wav_samples(&mut self.audio, &self.samples)
+    }
+}
+
+impl AudioCapture {
+    // start recording the stereo samples into the WAV file
+    fn start<P: AsRef<Path>>(path: P, sample_rate: u32) -> Result<Self> {
+        let wav = create_wav(&path, sample_rate, 2)?;
+        info!("Recording audio: {} at {} Hz", path.as_ref().display(), sample_rate);
+        Ok(AudioCapture { wav, samples: Vec::new() })
+    }
+
+    fn finish(self) -> Result<()> {
+        finalize_wav(self.wav)?;
+        info!("Audio recording stopped");
+        Ok(())
+    }
+
+    // record the audio frame rendered by the BLEP
+    fn record_frame(&mut self, blep: &mut BandLim, mixer: &Mixer) -> io::Result<()> {
+        produce_audio_frame(2, &mut self.samples, blep, mixer);
+        write_wav_samples(&mut self.wav, &self.samples)
+    }
+}
+
+// start or stop recording the audio into a time stamped WAV file in the current directory
+fn toggle_audio_capture(audio_capture: &mut Option<AudioCapture>, sample_rate: u32) -> Result<()> {
+    if let Some(capture) = audio_capture.take() {
+        capture.finish()
+    }
+    else {
+        let path = timestamped_path(".", "audio", "wav");
+        *audio_capture = Some(AudioCapture::start(path, sample_rate)?);
+        Ok(())
+    }
+}
+
+// tee the samples of the audio frame into the WAV file
+fn capture_audio_frame(audio_capture: &mut Option<AudioCapture>, blep: &mut BandLim, mixer: &Mixer) {
+    if let Some(capture) = audio_capture.as_mut() {
+        if let Err(err) = capture.record_frame(blep, mixer) {
+            error!("Couldn't record the audio frame: {}", err);
+            *audio_capture = None;
+        }
+    }
+}
+
+// the delay between GIF frames in 1/100 of a second
+const GIF_FRAME_DELAY: u16 = 2;
+
+impl Headless {
+    // without the script and the frames limit it runs forever
+    fn is_finished(&self) -> bool {
+        match (self.max_frames, self.script.as_ref()) {
+            (Some(max_frames), _) => self.frames >= max_frames,
+            (None, Some(script)) => script.is_finished(),
+            (None, None) => false
+        }
+    }
+
+    fn frame_passed(&mut self) {
+        self.frames += 1;
+        if let Some(script) = self.script.as_mut() {
+            script.frame_passed();
+        }
+    }
+}
+
+impl GifCapture {
+    fn new(replay_secs: usize, palette: Colors) -> Self {
+        GifCapture {
+            replay_frames: replay_secs * 50,
+            replay: None,
+            recording: None,
+            palette
+        }
+    }
+
+    // the clips are saved with the most recently selected palette
+    fn set_palette(&mut self, palette: Colors) {
+        self.palette = palette;
+        for clip in self.replay.iter_mut().chain(self.recording.iter_mut()) {
+            clip.set_palette(&palette);
+        }
+    }
+
+    // ensure the clips match the frame size, the clips of a different size are dropped
+    fn ensure_dimensions(&mut self, width: usize, height: usize) {
+        if self.recording.as_ref().map(|clip| clip.dimensions()) != Some((width, height)) {
+            if self.recording.take().is_some() {
+                warn!("The GIF recording was stopped, the frame size has changed");
+            }
+        }
+        if self.replay_frames != 0 &&
+                self.replay.as_ref().map(|clip| clip.dimensions()) != Some((width, height)) {
+            self.replay = Some(GifClip::new(width, height, &self.palette, Some(self.replay_frames)));
+        }
+    }
+
+    // push the frame of palette indices
+    fn push_frame(&mut self, indices: &[u32]) {
+        for clip in self.replay.iter_mut().chain(self.recording.iter_mut()) {
+            clip.push_indexed_frame(indices);
+        }
+    }
+
+    // the clips have up to 16 colours, so the frames in the ULAplus palette mode
+    // stop the recording and are left out of the replay
+    fn drop_frames(&mut self) {
+        if let Some(clip) = self.recording.take() {
+            warn!("The GIF recording was stopped, the ULAplus palette has more than 16 colours");
+            if !clip.is_empty() {
+                save_gif(&clip);
+            }
+        }
+        if let Some(clip) = self.replay.as_mut() {
+            clip.clear();
+        }
+    }
+
+    // save the most recent frames
+    fn save_replay(&mut self) {
+        match self.replay.as_ref() {
+            Some(clip) if !clip.is_empty() => save_gif(clip),
+            _ => warn!("There are no frames to save as GIF")
+        }
+    }
+
+    // start or stop and save the GIF recording
+    fn toggle_recording(&mut self, width: usize, height: usize) {
+        if let Some(clip) = self.recording.take() {
+            save_gif(&clip);
+        }
+        else {
+            info!("GIF recording started");
+            self.recording = Some(GifClip::new(width, height, &self.palette, None));
+        }
+    }
+}
+
+// save the GIF clip to a time stamped file in the current directory
+fn save_gif(clip: &GifClip) {
+    let path = timestamped_path(".", "clip", "gif");
+    match clip.save(&path, GIF_FRAME_DELAY) {
+        Ok(()) => info!("Saved {} frames to: {}", clip.len(), path.display()),
+        Err(err) => error!("Couldn't save the GIF: {} {}", path.display(), err)
+    }
+}
+
+// start or stop the video and audio recording
+fn toggle_av_capture(
+        av_capture: &mut Option<AvCapture>,
+        width: usize,
+        height: usize,
+        frame_rate: (u32, u32),
+        sample_rate: u32
+    ) -> Result<()>
+{
+    if let Some(capture) = av_capture.take() {
+        capture.finish()
+    }
+    else {
+        *av_capture = Some(AvCapture::start(width, height, frame_rate, sample_rate)?);
+        Ok(())
+    }
+}
+
+// record the frame with the audio from the BLEP, or with silence if there is no BLEP
+fn capture_av_frame(
+        av_capture: &mut Option<AvCapture>,
+        pixels: &[u32],
+        blep: Option<&mut BandLim>,
+        mixer: &Mixer
+    )
+{
+    if let Some(capture) = av_capture.as_mut() {
+        let res = match blep {
+            Some(blep) => capture.record_frame(pixels, blep, mixer),
+            None => capture.record_frame_silent(pixels)
+        };
+        if let Err(err) = res {
+            error!("Couldn't record the video frame: {}", err);
+            *av_capture = None;
+        }
+    }
+}
 
 fn run<C: Cpu, U>(
         spectrum: &mut ZxSpectrum<C, U>,
-        Env { window, width, height, border, pixels, audio, blep }: Env<'_>,
+        Env { window, width, height, border, window_mode, resampler, pixels, audio, blep, mixer,
+              av_capture, audio_capture, gif_capture, palettes, filters, blender, osd, settings }: Env<'_>,
     ) -> Result<Action>
-    where U: UlaCommon + UlaAudioFrame<BandLim> + DeviceAccess + HostConfig,
+    where U: UlaCommon + UlaAudioFrame<BandLim> + DeviceAccess + ScreenAccess + HostConfig,
           ZxSpectrum<C, U>: JoystickAccess
 
 {
-    window.set_title(&spectrum.info()?);
+    spectrum.update_status(window, osd, mixer)?;
 
     let app_menu = AppMenu::new(&window);
 
@@ -924,7 +2501,19 @@ fn run<C: Cpu, U>(
     spectrum.ula.ensure_audio_frame_time(blep, audio.sample_rate(), U::CPU_HZ as f64);
     audio.play()?;
 
+    // the exact frame rate of the emulated model
+    let frame_rate = (U::CPU_HZ, <U as Video>::VideoFrame::FRAME_TSTATES_COUNT as u32);
+    // the recording can't go on if the frame changes
+    if let Some(capture) = av_capture.as_ref() {
+        if capture.frame_rate != frame_rate || capture.video.dimensions() != (width, height) {
+            av_capture.take().unwrap().finish()?;
+        }
+    }
+
+    gif_capture.ensure_dimensions(width, height);
+
     let mut sync = ThreadSyncTimer::new(U::frame_duration_nanos());
+    let frame_duration = Duration::from_nanos(U::frame_duration_nanos().into());
     fn synchronize_frame(sync: &mut ThreadSyncTimer) {
         if let Err(missed) = sync.synchronize_thread_to_frame() {
             debug!("*** paused for: {} frames ***", missed);
@@ -937,31 +2526,32 @@ fn run<C: Cpu, U>(
 
     #[cfg(feature = "measure_cpu_freq")]
     measure_ticks_start!(time, dur, ticks, spectrum, U);
+    #[cfg(feature = "measure_cpu_freq")]
+    measure_cost_start!(filter_cost);
 
     // emulator main loop
     'main: while is_running(window) {
-        process_keyboard_window_events(window, |KeyEvent { key, pressed, shift_down, ctrl_down }| {
-            if !update_joystick_from_key_event(key, pressed, FIRE_KEY,
-                                                || spectrum.joystick_interface()) {
-                spectrum.update_keyboard(|keymap|
-                    update_keymap(keymap, key, pressed, shift_down, ctrl_down)
-                );
-                spectrum.update_keypad128_keys(|padmap|
-                    update_keypad_keys(padmap, key, pressed, shift_down || ctrl_down)
-                );
-            }
-        });
+        process_keyboard_window_events(window, |event| spectrum.update_from_key_event(event));
 
         let (_, mut state_changed) = if spectrum.state.paused {
-            window.limit_update_rate(Some(std::time::Duration::from_millis(100)));
+            window.limit_update_rate(Some(Duration::from_millis(100)));
             loop {
                 if !is_running(window) { break 'main; }
                 match app_menu.is_menu_pressed(window) {
                     Some(MENU_PAUSE_ID) => { break; }
                     Some(MENU_EXIT_ID) => { break 'main; }
+                    Some(menu @ (MENU_SCREENSHOT_ID|MENU_SCREENSHOT_SCREEN_ID)) => {
+                        save_screenshot(pixels, width, height, menu == MENU_SCREENSHOT_SCREEN_ID);
+                    }
                     _ => {}
                 }
-                window.update();
+                if osd.is_enabled() {
+                    // keep the OSD messages fading out
+                    update_display(window, pixels, width, height, resampler, osd)?;
+                }
+                else {
+                    window.update();
+                }
             }
             spectrum.state.paused = false;
             window.limit_update_rate(None);
@@ -978,16 +2568,117 @@ fn run<C: Cpu, U>(
         measure_ticks!(time, dur, ticks, spectrum, U);
 
         let (video_buffer, pitch) = acquire_video_buffer(pixels.as_mut(), width);
-        spectrum.render_video::<SpectrumPal>(video_buffer, pitch, border);
+        spectrum.render_video::<IndexPal>(video_buffer, pitch, border);
+        // the ULAplus palette takes precedence over the selected one
+        if spectrum.render_ulaplus(pixels, width, height) {
+            gif_capture.drop_frames();
+        }
+        else {
+            gif_capture.push_frame(pixels);
+            apply_palette(pixels, &palettes.current().colors);
+        }
+        if let Some(blender) = blender.as_mut() {
+            blender.blend(pixels);
+        }
+        #[cfg(feature = "measure_cpu_freq")]
+        measure_cost!(filter_cost, "CRT filters", filters.apply(pixels, width));
+        #[cfg(not(feature = "measure_cpu_freq"))]
+        filters.apply(pixels, width);
 
-        // update_display
-        window.update_with_buffer(&pixels, width, height)
-              .map_err(|e| e.to_string())?;
+        update_display(window, pixels, width, height, resampler, osd)?;
 
         if let Some(menu) = app_menu.is_menu_pressed(window) {
-            match spectrum.update_on_user_request(menu)? {
-                Some(action) => return Ok(action),
-                None => { state_changed = true; }
+            match menu {
+                MENU_SCREENSHOT_ID|MENU_SCREENSHOT_SCREEN_ID => {
+                    save_screenshot(pixels, width, height, menu == MENU_SCREENSHOT_SCREEN_ID);
+                }
+                MENU_RECORD_AV_ID => {
+                    toggle_av_capture(av_capture, width, height, frame_rate, audio.sample_rate())?;
+                }
+                MENU_RECORD_AUDIO_ID => {
+                    toggle_audio_capture(audio_capture, audio.sample_rate())?;
+                }
+                MENU_YM_VERSION_ID => {
+                    let version = match ym_version_setting(settings) {
+                        YmVersion::Ym5 => YmVersion::Ym6,
+                        YmVersion::Ym6 => YmVersion::Ym5
+                    };
+                    store_ym_version(settings, version);
+                    info!("AY log format: {}", ym_version_name(version));
+                }
+                MENU_AY_LOG_ID => {
+                    let version = ym_version_setting(settings);
+                    let (cpu_hz, frame_tstates) = frame_rate;
+                    let info = YmInfo {
+                        version,
+                        // the AY of the 128k is clocked at the half of the CPU clock
+                        clock: cpu_hz / 2,
+                        frame_rate: (cpu_hz as f64 / frame_tstates as f64).round() as u16,
+                        title: String::new(),
+                        author: String::new(),
+                        comment: format!("Logged from {}", spectrum.model_name())
+                    };
+                    if spectrum.state.ay_log.is_none() {
+                        spectrum.warn_unlogged_ay_chips();
+                    }
+                    spectrum.state.toggle_ay_log(info)?;
+                }
+                MENU_GIF_REPLAY_ID => { gif_capture.save_replay(); }
+                MENU_GIF_RECORD_ID => { gif_capture.toggle_recording(width, height); }
+                MENU_BORDER_ID => return Ok(Action::ChangeBorder(next_border_size(border))),
+                MENU_SCALE_ID..=MENU_SCALE_LAST_ID|
+                MENU_SCALE_NEXT_ID|MENU_PAL_ASPECT_ID|MENU_FULLSCREEN_ID => {
+                    return Ok(Action::ChangeWindow(update_window_mode(menu, window_mode)))
+                }
+                MENU_PALETTE_NEXT_ID|MENU_PALETTE_LOAD_ID|
+                MENU_PALETTE_ID..=MENU_PALETTE_LAST_ID => {
+                    update_palette_on_user_request(menu, palettes, settings);
+                    gif_capture.set_palette(palettes.current().colors);
+                }
+                MENU_AY_STEREO_ID..=MENU_AY_STEREO_LAST_ID|MENU_AY_STEREO_NEXT_ID|
+                MENU_SEPARATION_DEC_ID|MENU_SEPARATION_INC_ID => {
+                    update_mixer_on_user_request(menu, mixer, settings);
+                    state_changed = true;
+                }
+                MENU_TURBOSOUND_ID => {
+                    spectrum.toggle_turbosound();
+                    settings.store("turbosound", spectrum.state.turbosound);
+                    state_changed = true;
+                }
+                MENU_AUDIO_PACING_ID => {
+                    let enabled = audio.set_pacing(!audio.is_pacing());
+                    info!("Frame pacing: {}", if enabled { "audio" } else { "timer" });
+                    settings.store("audio_pacing", enabled);
+                    // back to the nominal clock rate
+                    spectrum.ula.ensure_audio_frame_time(blep, audio.sample_rate(), U::CPU_HZ as f64);
+                    sync.restart();
+                }
+                MENU_MUTE_ID|MENU_VOLUME_DEC_ID|MENU_VOLUME_INC_ID|
+                MENU_LEVEL_ID..=MENU_LEVEL_LAST_ID => {
+                    update_volume_on_user_request(menu, mixer, settings);
+                    state_changed = true;
+                }
+                MENU_OSD_ID => {
+                    osd.set_enabled(!osd.is_enabled());
+                    settings.store("osd", osd.is_enabled());
+                    state_changed = true;
+                }
+                MENU_BLEND_ID => {
+                    *blender = match blender.take() {
+                        Some(_) => None,
+                        None => Some(FrameBlender::new())
+                    };
+                    info!("Frame blending: {}", if blender.is_some() { "on" } else { "off" });
+                }
+                MENU_FILTER_ID..=MENU_FILTER_LAST_ID => {
+                    let filter = CrtFilter::ALL[menu - MENU_FILTER_ID];
+                    let enabled = filters.toggle(filter);
+                    info!("{}: {}", filter, if enabled { "on" } else { "off" });
+                }
+                menu => match spectrum.update_on_user_request(menu)? {
+                    Some(action) => return Ok(action),
+                    None => { state_changed = true; }
+                }
             }
         }
 
@@ -1001,19 +2692,31 @@ fn run<C: Cpu, U>(
                 // otherwise this thread will hang forever waiting for the response
                 audio.play()?;
             }
-            window.set_title(&spectrum.info()?);
+            spectrum.update_status(window, osd, mixer)?;
         }
 
         if !spectrum.state.turbo && !spectrum.state.paused {
             // no audio in TURBO mode or when PAUSED
-            spectrum.render_audio(blep);
+            spectrum.render_audio(blep, mixer.ay_channels());
             // (3) render the BLEP frame as audio samples
-            produce_and_send_audio_frame(audio, blep)?;
+            audio.send_frame(blep, mixer, frame_duration)?;
+            capture_audio_frame(audio_capture, blep, mixer);
+            capture_av_frame(av_capture, pixels, Some(blep), mixer);
             // (4) prepare the BLEP for the next frame.
             blep.next_frame();
+            // the frames paced by the audio keep the real-time speed with a little more
+            // or a little less samples rendered per frame
+            if let Some(ratio) = audio.pacing_ratio() {
+                spectrum.ula.ensure_audio_frame_time(blep, audio.sample_rate(), U::CPU_HZ as f64 * ratio);
+            }
+        }
+        else if spectrum.state.turbo {
+            // the TURBO frames are recorded as displayed, with silence
+            capture_av_frame(av_capture, pixels, None, mixer);
         }
 
-        if !spectrum.state.turbo {
+        // the timer paces the frames unless the audio does it
+        if !spectrum.state.turbo && !audio.is_pacing() {
             synchronize_frame(&mut sync);
         }
     }
@@ -1021,20 +2724,210 @@ fn run<C: Cpu, U>(
     Ok(Action::Exit)
 }
 
+// press or release the keys on behalf of the script, the keys held down
+// act as the modifiers the same way they do in the window
+fn press_script_keys<C: Cpu, U>(
+        spectrum: &mut ZxSpectrum<C, U>,
+        keys_down: &mut Vec<Key>,
+        keys: Vec<Key>,
+        pressed: bool
+    )
+    where U: UlaCommon + DeviceAccess,
+          ZxSpectrum<C, U>: JoystickAccess
+{
+    for key in keys {
+        keys_down.retain(|&k| k != key);
+        if pressed {
+            keys_down.push(key);
+        }
+        let shift_down = keys_down.contains(&Key::LeftShift) || keys_down.contains(&Key::RightShift);
+        let ctrl_down = keys_down.contains(&Key::LeftCtrl);
+        spectrum.update_from_key_event(KeyEvent { key, pressed, shift_down, ctrl_down });
+    }
+}
+
+// the same emulation as in `run`, but without the window, the audio device and the real-time pacing,
+// the frames are rendered into `pixels` only and the audio frames are discarded
+fn run_headless<C: Cpu, U>(
+        spectrum: &mut ZxSpectrum<C, U>,
+        HeadlessEnv { width, height, border, pixels, blep, mixer, palettes, headless }: HeadlessEnv<'_>,
+    ) -> Result<Action>
+    where U: UlaCommon + UlaAudioFrame<BandLim> + DeviceAccess + ScreenAccess + HostConfig,
+          ZxSpectrum<C, U>: JoystickAccess
+{
+    info!("Running headless: {}", spectrum.info()?);
+    let sample_rate = headless.sample_rate.unwrap_or(NULL_SAMPLE_RATE);
+    spectrum.ula.ensure_audio_frame_time(blep, sample_rate, U::CPU_HZ as f64);
+
+    loop {
+        while let Some(command) = headless.script.as_mut().and_then(Script::next_command) {
+            let menu_id = match command {
+                Command::Wait(..) => continue,
+                Command::Press(keys) => {
+                    press_script_keys(spectrum, &mut headless.keys_down, keys, true);
+                    continue
+                }
+                Command::Release(keys) => {
+                    press_script_keys(spectrum, &mut headless.keys_down, keys, false);
+                    continue
+                }
+                Command::Screenshot { path, no_border } => {
+                    save_screenshot_to(&path, pixels, width, height, no_border);
+                    continue
+                }
+                Command::RecordAudio(path) => {
+                    if let Some(capture) = headless.audio_capture.take() {
+                        capture.finish()?;
+                    }
+                    headless.audio_capture = Some(AudioCapture::start(path, sample_rate)?);
+                    continue
+                }
+                Command::StopAudio => {
+                    if let Some(capture) = headless.audio_capture.take() {
+                        capture.finish()?;
+                    }
+                    continue
+                }
+                Command::Tape(TapeCommand::Insert(path)) => {
+                    spectrum.insert_tape(path)?;
+                    continue
+                }
+                Command::Model(16)         => MENU_MODEL_16_ID,
+                Command::Model(48)         => MENU_MODEL_48_ID,
+                Command::Model(_)          => MENU_MODEL_128_ID,
+                Command::Reset { hard }    => if hard { MENU_HARD_RESET_ID } else { MENU_SOFT_RESET_ID },
+                Command::Nmi               => MENU_TRIG_NMI_ID,
+                Command::Tape(TapeCommand::Play)   => MENU_TAPE_PLAY_ID,
+                Command::Tape(TapeCommand::Stop)   => MENU_TAPE_STOP_ID,
+                Command::Tape(TapeCommand::Rewind) => MENU_TAPE_REWIND_ID,
+                Command::Tape(TapeCommand::Next)   => MENU_TAPE_NEXT_ID,
+                Command::Tape(TapeCommand::Prev)   => MENU_TAPE_PREV_ID,
+                Command::Tape(TapeCommand::Eject)  => MENU_TAPE_EJECT_ID,
+                Command::Exit              => MENU_EXIT_ID
+            };
+            if let Some(action) = spectrum.update_on_user_request(menu_id)? {
+                return Ok(action)
+            }
+        }
+
+        if headless.is_finished() {
+            return Ok(Action::Exit)
+        }
+
+        spectrum.run_frame()?;
+
+        let (video_buffer, pitch) = acquire_video_buffer(pixels.as_mut(), width);
+        spectrum.render_video::<IndexPal>(video_buffer, pitch, border);
+        if !spectrum.render_ulaplus(pixels, width, height) {
+            apply_palette(pixels, &palettes.current().colors);
+        }
+        // the audio is rendered as usual, but the samples go nowhere unless recorded
+        spectrum.render_audio(blep, mixer.ay_channels());
+        capture_audio_frame(&mut headless.audio_capture, blep, mixer);
+        blep.next_frame();
+
+        headless.frame_passed();
+    }
+}
+
+// run the emulation with no window and no audio, until the script or the requested frames end
+fn run_headless_loop(
+        mut spectrum: ZxSpectrumModel<Z80NMOS, PluggableUlaPlusBusDevice>,
+        mut headless: Headless,
+        border: BorderSize,
+        palettes: &Palettes,
+        mixer: &Mixer
+    ) -> Result<()>
+{
+    let (width, height) = render_size(border);
+    let mut pixels: Vec<u32> = vec![0; width * height];
+    let mut blep = BandLimited::<BlepDelta>::new(BLEP_CHANNELS);
+
+    loop {
+        use ZxSpectrumModel::*;
+        let env = HeadlessEnv { width, height, border,
+                                pixels: &mut pixels,
+                                blep: &mut blep,
+                                mixer,
+                                palettes,
+                                headless: &mut headless };
+
+        let req = match &mut spectrum {
+            Spectrum16(spec16) => run_headless(spec16, env)?,
+            Spectrum48(spec48) => run_headless(spec48, env)?,
+            Spectrum128(spec128) => run_headless(spec128, env)?
+        };
+
+        spectrum = match req {
+            Action::ChangeModel(spec) => spectrum.change_model(spec),
+            Action::Exit => break,
+            _ => spectrum
+        };
+    }
+    if let Some(capture) = headless.audio_capture.take() {
+        capture.finish()?;
+    }
+    info!("Headless run finished after {} frames", headless.frames);
+    Ok(())
+}
+
+// width and height of the rendered frame image area in pixels, more convenient for minifb
+fn render_size(border: BorderSize) -> (usize, usize) {
+    let (width, height) = <Ula128 as Video>::render_size_pixels(border);
+    (width as usize, height as usize)
+}
+
+// the YM format version of the AY log, YM6 by default
+fn ym_version_setting(settings: &Settings) -> YmVersion {
+    match settings.parse::<u8>("ym_version") {
+        Some(5) => YmVersion::Ym5,
+        _ => YmVersion::Ym6
+    }
+}
+
+fn store_ym_version(settings: &mut Settings, version: YmVersion) {
+    settings.store("ym_version", match version {
+        YmVersion::Ym5 => 5,
+        YmVersion::Ym6 => 6
+    });
+}
+
+fn ym_version_name(version: YmVersion) -> &'static str {
+    match version {
+        YmVersion::Ym5 => "YM5",
+        YmVersion::Ym6 => "YM6"
+    }
+}
+
 fn show_help() -> Result<()> {
-    eprintln!("{}: [-16|48|128] [-b BORDER] [-j JOYSTICK] [TAPFILE]",
+    eprintln!("{}: [-16|48|128] [-b BORDER] [-j JOYSTICK] [-a AYIFACE] [-T] [-g GIFSECS] [-P PALETTE|FILE] [-r RECDIR] [-p|-pb PULSES|-] [-w WAVFILE] [-Y 5|6] [-H] [-n FRAMES] [-s SCRIPT|-] [-L LATENCY] [-A] [-R RATE] [-F i16|u16|f32] [-D DEVICE] [TAPFILE|ZIPFILE[#ENTRY]]",
             std::env::args().next().as_deref().unwrap_or("step5"));
     Ok(())
 }
 
 fn main() -> Result<()> {
-    simple_logger::SimpleLogger::new().with_level(log::LevelFilter::Info).init()?;
+    // the log messages of this program are shown on the OSD as well
+    let messages = OsdLogger::init(simple_logger::SimpleLogger::new().with_level(log::LevelFilter::Info),
+                                   log::LevelFilter::Info,
+                                   module_path!())?;
     spectrusty_tutorial::set_dpi_awareness()?;
     let mut args = std::env::args().skip(1);
     let mut border = BorderSize::Full;
     let mut model = ModelReq::Spectrum128;
     let mut joystick = None;
+    let mut ay_interface = None;
+    let mut turbosound = false;
     let mut tap_file_name = None;
+    let mut record_dir = None;
+    let mut pulse_stream = None;
+    let mut script_path = None;
+    let mut gif_replay_secs = 10;
+    let mut palette = None;
+    let mut headless: Option<Headless> = None;
+    let mut wav_file_name = None;
+    let mut audio_config = AudioConfig::default();
+    let mut audio_pacing = false;
+    let mut ym_version = None;
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "-16" =>  { model = ModelReq::Spectrum16; },
@@ -1044,6 +2937,74 @@ fn main() -> Result<()> {
                 Some(arg) => { border = arg.parse()?; },
                 None => return show_help()
             },
+            "-g" => match args.next() {
+                Some(arg) => { gif_replay_secs = arg.parse()?; },
+                None => return show_help()
+            },
+            "-P" => match args.next() {
+                Some(arg) => { palette = Some(arg); },
+                None => return show_help()
+            },
+            // -n and -s imply the headless mode
+            "-H" => { headless.get_or_insert_with(Headless::default); },
+            "-n" => match args.next() {
+                Some(arg) => { headless.get_or_insert_with(Headless::default).max_frames = Some(arg.parse()?); },
+                None => return show_help()
+            },
+            "-s" => match args.next() {
+                Some(path) => {
+                    headless.get_or_insert_with(Headless::default);
+                    script_path = Some(path);
+                },
+                None => return show_help()
+            },
+            "-L" => match args.next() {
+                Some(arg) => { audio_config.latency = Some(arg.parse()?); },
+                None => return show_help()
+            },
+            "-A" => { audio_pacing = true; },
+            "-R" => match args.next() {
+                Some(arg) => { audio_config.sample_rate = Some(arg.parse()?); },
+                None => return show_help()
+            },
+            "-F" => match args.next() {
+                Some(arg) => match parse_sample_format(&arg) {
+                    Some(format) => { audio_config.sample_format = Some(format); },
+                    None => {
+                        eprintln!("Unknown sample format: \"{}\", choose from: i16|u16|f32", arg);
+                        return Ok(());
+                    }
+                },
+                None => return show_help()
+            },
+            "-D" => match args.next() {
+                Some(name) => { audio_config.device = Some(name); },
+                None => return show_help()
+            },
+            "-Y" => match args.next().as_deref() {
+                Some("5") => { ym_version = Some(YmVersion::Ym5); },
+                Some("6") => { ym_version = Some(YmVersion::Ym6); },
+                Some(arg) => {
+                    eprintln!("Unknown YM version: \"{}\", choose from: 5|6", arg);
+                    return Ok(());
+                },
+                None => return show_help()
+            },
+            "-w" => match args.next() {
+                Some(path) => { wav_file_name = Some(path); },
+                None => return show_help()
+            },
+            "-r" => match args.next() {
+                Some(dir) => { record_dir = Some(dir); },
+                None => return show_help()
+            },
+            "-p"|"-pb" => match args.next() {
+                Some(path) => {
+                    let format = if arg == "-pb" { PulseFormat::Binary } else { PulseFormat::Text };
+                    pulse_stream = Some((path, format));
+                },
+                None => return show_help()
+            },
             "-j" => if let Some(joy) = args.next() {
                 joystick = if joy.eq_ignore_ascii_case("N")  { None }
                 else if joy.eq_ignore_ascii_case("K") { Some(0) }
@@ -1059,6 +3020,20 @@ fn main() -> Result<()> {
             else {
                 return show_help();
             },
+            "-T" => { turbosound = true; },
+            "-a" => if let Some(iface) = args.next() {
+                ay_interface = if iface.eq_ignore_ascii_case("N") { None }
+                else if iface.eq_ignore_ascii_case("M") { Some(AyInterface::Melodik) }
+                else if iface.eq_ignore_ascii_case("F") { Some(AyInterface::FullerBox) }
+                else if iface.eq_ignore_ascii_case("Z") { Some(AyInterface::ZxAy) }
+                else {
+                    eprintln!("Unknown AY interface: \"{}\", choose from: N|M|F|Z", iface);
+                    return Ok(());
+                };
+            }
+            else {
+                return show_help();
+            },
             x if x == "" || x.starts_with("-") => return show_help(),
             // parsing the 1st command argument as path to the TAP file
             name => {
@@ -1068,34 +3043,63 @@ fn main() -> Result<()> {
         };
     }
 
+    // both would read the standard input
+    if script_path.as_deref() == Some("-") &&
+            pulse_stream.as_ref().map(|(path, _)| path == "-").unwrap_or(false) {
+        eprintln!("The script and the pulse stream can't both be read from the standard input");
+        return Ok(());
+    }
+    // the split recording replaces the inserted tape
+    if record_dir.is_some() && tap_file_name.is_some() {
+        eprintln!("The tape can't be inserted while recording into a directory with -r");
+        return Ok(());
+    }
+    if let Some(path) = script_path {
+        headless.get_or_insert_with(Headless::default).script = Some(Script::load(path)?);
+    }
+
+    // the settings from the previous run
+    let mut settings = Settings::load();
+    // the YM version from the command line is remembered
+    if let Some(version) = ym_version {
+        store_ym_version(&mut settings, version);
+    }
+    let audio_config = setup_audio_config(&settings, audio_config);
+    let mut palettes = setup_palettes(&settings, palette)?;
+    // the AY stereo layout and the stereo separation
+    let mut mixer = setup_mixer(&settings);
+    // the post-processing of the rendered frames
+    let mut filters = CrtFilters::new();
+    // the blending of the consecutive frames, off by default
+    let mut blender = None;
+    // the on-screen display with the ROM font
+    let mut osd = Osd::new(&ROM48[ROM_FONT_ADDRESS..ROM_FONT_ADDRESS + FONT_SIZE], messages);
+    osd.set_enabled(settings.parse("osd").unwrap_or(true));
+
     // build the hardware
     let mut spec128 = ZxSpectrum128k::<Z80NMOS,
-                                       PluggableMultiJoyBusDevice
+                                       PluggableUlaPlusBusDevice
                                       >::new_with_rom();
     // if the user provided the file name
     if let Some(file_name) = tap_file_name {
         spec128.insert_tape(file_name)?;
     }
-
-    // width and height of the rendered frame image area in pixels
-    let (width, height) = <Ula128 as Video>::render_size_pixels(border);
-    // more convenient for minifb
-    let (width, height) = (width as usize, height as usize);
-    // minifb uses u32 XRGB pixels
-    let mut pixels: Vec<u32> = vec![0; width * height];
-    // open window
-    let mut window = open_window("ZX Spectrum", width, height)?;
-
-    // initialize audio
-    let frame_duration_nanos = <Ula128 as HostConfig>::frame_duration_nanos();
-    // first the audio handle with the embedded carousel
-    let mut audio = Audio::create(&cpal::default_host(), frame_duration_nanos, AUDIO_LATENCY)?;
-    // second the Bandwidth-Limited Pulse Buffer implementation
-    let mut blep = BlepStereo::build(0.8)(BandLimited::<BlepDelta>::new(2));
+    // feed EAR IN with the pulse stream
+    if let Some((path, format)) = pulse_stream {
+        spec128.open_pulse_stream(path, format);
+    }
+    // record saved programs into separate files
+    if let Some(dir) = record_dir {
+        spec128.record_tape_into_dir(dir)?;
+    }
 
     if let Some(joy) = joystick {
         spec128.select_joystick(joy);
     }
+    // the second AY chip
+    if turbosound || settings.parse("turbosound").unwrap_or(false) {
+        spec128.enable_turbosound(true);
+    }
 
     let mut spectrum = ZxSpectrumModel::Spectrum128(spec128);
 
@@ -1103,13 +3107,71 @@ fn main() -> Result<()> {
         spectrum = spectrum.change_model(model);
     }
 
+    // the Melodik and the ZX-AY are refused by the 128k
+    if ay_interface.is_some() {
+        match &mut spectrum {
+            ZxSpectrumModel::Spectrum16(spec16) => spec16.select_ay_interface(ay_interface),
+            ZxSpectrumModel::Spectrum48(spec48) => spec48.select_ay_interface(ay_interface),
+            ZxSpectrumModel::Spectrum128(spec128) => spec128.select_ay_interface(ay_interface)
+        }
+    }
+
+    if let Some(mut headless) = headless {
+        headless.sample_rate = audio_config.sample_rate;
+        if let Some(path) = wav_file_name {
+            let rate = audio_config.sample_rate.unwrap_or(NULL_SAMPLE_RATE);
+            headless.audio_capture = Some(AudioCapture::start(path, rate)?);
+        }
+        return run_headless_loop(spectrum, headless, border, &palettes, &mixer)
+    }
+
+    // width and height of the rendered frame image area in pixels
+    let (mut width, mut height) = render_size(border);
+    // minifb uses u32 XRGB pixels
+    let mut pixels: Vec<u32> = vec![0; width * height];
+    // the window presentation is kept when the model or the border size changes
+    let mut window_mode = WindowMode::default();
+    // open window
+    let (mut window, mut resampler) = open_display(width, height, window_mode)?;
+
+    // initialize audio
+    let frame_duration_nanos = <Ula128 as HostConfig>::frame_duration_nanos();
+    // first the audio handle with the embedded carousel
+    let mut audio = Audio::create_or_null(frame_duration_nanos, &audio_config);
+    // the audio paces the frames if chosen with -A or previously and there is the audio output
+    if (audio_pacing || settings.parse("audio_pacing").unwrap_or(false)) && !audio.set_pacing(true) {
+        warn!("No audio output, the frames are paced by the timer");
+    }
+    // second the Bandwidth-Limited Pulse Buffer implementation
+    let mut blep = BandLimited::<BlepDelta>::new(BLEP_CHANNELS);
+
+    // the video and audio recording
+    let mut av_capture = None;
+    // the audio recording, at the rate of the audio output
+    let mut audio_capture = match wav_file_name {
+        Some(path) => Some(AudioCapture::start(path, audio.sample_rate())?),
+        None => None
+    };
+    // the animated GIF clips
+    let mut gif_capture = GifCapture::new(gif_replay_secs, palettes.current().colors);
+
     loop {
         use ZxSpectrumModel::*;
-        let env = Env { width, height, border,
+        let env = Env { width, height, border, window_mode,
+                        resampler: &mut resampler,
                         window: &mut window, 
                         pixels: &mut pixels,
                         audio: &mut audio,
-                        blep: &mut blep };
+                        blep: &mut blep,
+                        mixer: &mut mixer,
+                        av_capture: &mut av_capture,
+                        audio_capture: &mut audio_capture,
+                        gif_capture: &mut gif_capture,
+                        palettes: &mut palettes,
+                        filters: &mut filters,
+                        blender: &mut blender,
+                        osd: &mut osd,
+                        settings: &mut settings };
 
         let req = match &mut spectrum {
             Spectrum16(spec16) => run(spec16, env)?,
@@ -1119,9 +3181,41 @@ fn main() -> Result<()> {
 
         spectrum = match req {
             Action::ChangeModel(spec) => spectrum.change_model(spec),
+            Action::ChangeBorder(new_border) => {
+                info!("Border size: {:?}", new_border);
+                border = new_border;
+                // the machine keeps running, only the frame and the window change their size
+                let (new_width, new_height) = render_size(border);
+                if (new_width, new_height) != (width, height) {
+                    width = new_width;
+                    height = new_height;
+                    pixels = vec![0; width * height];
+                    let (new_window, new_resampler) = open_display(width, height, window_mode)?;
+                    window = new_window;
+                    resampler = new_resampler;
+                }
+                spectrum
+            }
+            Action::ChangeWindow(new_mode) => {
+                info!("Window: {:?}", new_mode);
+                window_mode = new_mode;
+                let (new_window, new_resampler) = open_display(width, height, window_mode)?;
+                window = new_window;
+                resampler = new_resampler;
+                spectrum
+            }
             Action::Exit => break
         };
     }
 
+    if let Some(capture) = av_capture.take() {
+        capture.finish()?;
+    }
+    if let Some(capture) = audio_capture.take() {
+        capture.finish()?;
+    }
+    spectrum.state_mut().finish_ay_log()?;
+    audio.report_xruns();
+
     Ok(())
 }
@@ -0,0 +1,318 @@
+//! Capturing of the emulated frames and audio to files.
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The size of the ZX Spectrum screen without the border in pixels.
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 192;
+
+/// Returns a path to a new file in `dir` named `PREFIX-YYYYmmdd-HHMMSS-mmm.EXT`,
+/// the time stamp is in UTC.
+pub fn timestamped_path<P: AsRef<Path>>(dir: P, prefix: &str, ext: &str) -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let time = secs % 86400;
+    let name = format!("{}-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.{}",
+                       prefix, year, month, day,
+                       time / 3600, time / 60 % 60, time % 60,
+                       now.subsec_millis(), ext);
+    dir.as_ref().join(name)
+}
+
+// converts days since the UNIX epoch to the (year, month, day) of the Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Returns the pixels of the screen area without the border from the frame
+/// of `width` x `height` pixels.
+pub fn crop_border(pixels: &[u32], width: usize, height: usize) -> Vec<u32> {
+    let left = width.saturating_sub(SCREEN_WIDTH) / 2;
+    let top = height.saturating_sub(SCREEN_HEIGHT) / 2;
+    let cols = width.min(SCREEN_WIDTH);
+    pixels.chunks(width).skip(top).take(SCREEN_HEIGHT)
+          .flat_map(|line| &line[left..left + cols])
+          .copied()
+          .collect()
+}
+
+/// Saves XRGB pixels as a PNG image.
+pub fn save_png<P: AsRef<Path>>(
+        path: P,
+        pixels: &[u32],
+        width: usize,
+        height: usize
+    ) -> io::Result<()>
+{
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    let data: Vec<u8> = pixels.iter()
+                              .flat_map(|&p| [(p >> 16) as u8, (p >> 8) as u8, p as u8])
+                              .collect();
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Writes XRGB frames as an uncompressed YUV4MPEG2 video with 4:4:4 chroma.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    planes: Vec<u8>
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the stream header, the `frame_rate` is given as a `(numerator, denominator)` fraction.
+    pub fn new(mut writer: W, width: usize, height: usize, frame_rate: (u32, u32)) -> io::Result<Self> {
+        writeln!(writer, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                 width, height, frame_rate.0, frame_rate.1)?;
+        let planes = vec![0; width * height * 3];
+        Ok(Y4mWriter { writer, width, height, planes })
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Converts the frame pixels to the BT.601 YUV and writes them out.
+    pub fn write_frame(&mut self, pixels: &[u32]) -> io::Result<()> {
+        let size = self.width * self.height;
+        let (y_plane, uv_planes) = self.planes.split_at_mut(size);
+        let (u_plane, v_plane) = uv_planes.split_at_mut(size);
+        for (i, &pixel) in pixels.iter().take(size).enumerate() {
+            let (y, u, v) = rgb_to_yuv(pixel);
+            y_plane[i] = y;
+            u_plane[i] = u;
+            v_plane[i] = v;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&self.planes)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// converts XRGB pixel to the limited range BT.601 YUV
+fn rgb_to_yuv(pixel: u32) -> (u8, u8, u8) {
+    let (r, g, b) = ((pixel >> 16 & 0xFF) as i32, (pixel >> 8 & 0xFF) as i32, (pixel & 0xFF) as i32);
+    let y = (( 66 * r + 129 * g +  25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r -  74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r -  94 * g -  18 * b + 128) >> 8) + 128;
+    (y as u8, u as u8, v as u8)
+}
+
+/// The WAV file writer.
+pub type WavWriter = hound::WavWriter<BufWriter<File>>;
+
+/// Creates a new WAV file for 16-bit PCM samples.
+pub fn create_wav<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> io::Result<WavWriter> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int
+    };
+    WavWriter::create(path, spec).map_err(wav_error)
+}
+
+/// Writes interleaved samples to the WAV file.
+pub fn write_wav_samples(wav: &mut WavWriter, samples: &[i16]) -> io::Result<()> {
+    let mut writer = wav.get_i16_writer(samples.len() as u32);
+    for &sample in samples {
+        writer.write_sample(sample);
+    }
+    writer.flush().map_err(wav_error)
+}
+
+/// Updates the WAV header and closes the file.
+pub fn finalize_wav(wav: WavWriter) -> io::Result<()> {
+    wav.finalize().map_err(wav_error)
+}
+
+fn wav_error(err: hound::Error) -> io::Error {
+    match err {
+        hound::Error::IoError(err) => err,
+        err => io::Error::other(err)
+    }
+}
+
+/// The clip of frames with up to 16 colours to be saved as an animated GIF.
+///
+/// Frames are kept as palette indices, so a short clip takes little memory
+/// and the GIF doesn't need any colour quantization.
+pub struct GifClip {
+    width: usize,
+    height: usize,
+    palette: Vec<u32>,
+    max_frames: Option<usize>,
+    frames: VecDeque<Vec<u8>>
+}
+
+impl GifClip {
+    /// Creates a new clip with the given `palette` of up to 16 XRGB colours.
+    ///
+    /// If `max_frames` is given only the most recent frames are kept.
+    pub fn new(width: usize, height: usize, palette: &[u32], max_frames: Option<usize>) -> Self {
+        GifClip {
+            width,
+            height,
+            palette: palette.iter().take(16).copied().collect(),
+            max_frames,
+            frames: VecDeque::new()
+        }
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Replaces the palette, the new colours apply to all the frames of the clip.
+    pub fn set_palette(&mut self, palette: &[u32]) {
+        self.palette = palette.iter().take(16).copied().collect();
+    }
+
+    /// Appends the XRGB frame, the colours not found in the palette become colour 0.
+    pub fn push_frame(&mut self, pixels: &[u32]) {
+        let mut frame = self.next_frame();
+        let mut last = (0, 0u8);
+        frame.extend(pixels.iter().take(self.width * self.height).map(|&pixel| {
+            if pixel != last.0 {
+                let index = self.palette.iter().position(|&p| p == pixel).unwrap_or(0);
+                last = (pixel, index as u8);
+            }
+            last.1
+        }));
+        self.frames.push_back(frame);
+    }
+
+    /// Appends the frame of palette indices.
+    pub fn push_indexed_frame(&mut self, indices: &[u32]) {
+        let mut frame = self.next_frame();
+        frame.extend(indices.iter().take(self.width * self.height).map(|&index| (index & 15) as u8));
+        self.frames.push_back(frame);
+    }
+
+    // returns the emptied buffer for the next frame, reusing the oldest one if the clip is full
+    fn next_frame(&mut self) -> Vec<u8> {
+        let mut frame = match self.max_frames {
+            Some(max) if self.frames.len() >= max => self.frames.pop_front().unwrap(),
+            _ => Vec::with_capacity(self.width * self.height)
+        };
+        frame.clear();
+        frame
+    }
+
+    /// Writes the clip as an endlessly looped animated GIF with `delay` between frames
+    /// in hundredths of a second.
+    ///
+    /// Only the changed areas of the frames are written and the delays of identical
+    /// frames are merged.
+    pub fn save<P: AsRef<Path>>(&self, path: P, delay: u16) -> io::Result<()> {
+        let mut global_palette = Vec::with_capacity(16 * 3);
+        for index in 0..16 {
+            let color = self.palette.get(index).copied().unwrap_or(0);
+            global_palette.extend_from_slice(&[(color >> 16) as u8, (color >> 8) as u8, color as u8]);
+        }
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = gif::Encoder::new(file, self.width as u16, self.height as u16, &global_palette)
+                                       .map_err(gif_error)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+        let mut prev: Option<&Vec<u8>> = None;
+        let mut pending: Option<gif::Frame> = None;
+        for frame in self.frames.iter() {
+            let (left, top, width, height) = match prev {
+                None => (0, 0, self.width, self.height),
+                Some(prev) => match changed_area(prev, frame, self.width) {
+                    Some(area) => area,
+                    None => {
+                        if let Some(pending) = pending.as_mut() {
+                            pending.delay = pending.delay.saturating_add(delay);
+                        }
+                        continue
+                    }
+                }
+            };
+            if let Some(pending) = pending.take() {
+                encoder.write_frame(&pending).map_err(gif_error)?;
+            }
+            let buffer: Vec<u8> = frame.chunks(self.width).skip(top).take(height)
+                                       .flat_map(|line| &line[left..left + width])
+                                       .copied()
+                                       .collect();
+            pending = Some(gif::Frame {
+                delay,
+                dispose: gif::DisposalMethod::Keep,
+                left: left as u16,
+                top: top as u16,
+                width: width as u16,
+                height: height as u16,
+                buffer: Cow::Owned(buffer),
+                ..gif::Frame::default()
+            });
+            prev = Some(frame);
+        }
+        if let Some(pending) = pending {
+            encoder.write_frame(&pending).map_err(gif_error)?;
+        }
+        encoder.into_inner()?.flush()
+    }
+}
+
+// returns the (left, top, width, height) of the area that differs between frames
+fn changed_area(prev: &[u8], next: &[u8], width: usize) -> Option<(usize, usize, usize, usize)> {
+    let (mut left, mut right, mut top, mut bottom) = (usize::MAX, 0, usize::MAX, 0);
+    for (y, (pline, nline)) in prev.chunks(width).zip(next.chunks(width)).enumerate() {
+        if let Some(x0) = pline.iter().zip(nline).position(|(p, n)| p != n) {
+            let x1 = width - pline.iter().zip(nline).rev().position(|(p, n)| p != n).unwrap();
+            left = left.min(x0);
+            right = right.max(x1);
+            top = top.min(y);
+            bottom = y + 1;
+        }
+    }
+    if top == usize::MAX {
+        None
+    }
+    else {
+        Some((left, top, right - left, bottom - top))
+    }
+}
+
+fn gif_error(err: gif::EncodingError) -> io::Error {
+    match err {
+        gif::EncodingError::Io(err) => err,
+        err => io::Error::other(err)
+    }
+}
@@ -0,0 +1,231 @@
+//! CRT-style post-processing filters applied to the rendered XRGB frame on the CPU.
+//!
+//! The filters are applied in the order: PAL colour bleed, horizontal blur,
+//! phosphor ghosting and scanlines.
+//!
+//! The [FrameBlender] is kept apart from the filters, as it changes what is seen
+//! rather than how it looks.
+use core::fmt;
+
+/// A single filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrtFilter {
+    /// Every other line is darkened.
+    Scanlines,
+    /// Each pixel is blended with its horizontal neighbours.
+    HorizontalBlur,
+    /// The colour is smeared horizontally, while the brightness stays sharp.
+    PalBleed,
+    /// The bright pixels of the previous frames fade out slowly.
+    Ghosting
+}
+
+impl CrtFilter {
+    pub const ALL: [CrtFilter; 4] = [
+        CrtFilter::Scanlines,
+        CrtFilter::HorizontalBlur,
+        CrtFilter::PalBleed,
+        CrtFilter::Ghosting
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CrtFilter::Scanlines => "Scanlines",
+            CrtFilter::HorizontalBlur => "Horizontal blur",
+            CrtFilter::PalBleed => "PAL colour bleed",
+            CrtFilter::Ghosting => "Phosphor ghosting"
+        }
+    }
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for CrtFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// how many pixels to each side the colour bleeds
+const BLEED_RADIUS: usize = 2;
+
+/// The set of enabled filters with the buffers they need.
+#[derive(Debug, Clone, Default)]
+pub struct CrtFilters {
+    enabled: u8,
+    // a copy of the line being filtered
+    line: Vec<u32>,
+    // the chroma of the line being filtered
+    chroma: Vec<(i32, i32)>,
+    // the previous frame for the ghosting
+    prev: Vec<u32>
+}
+
+impl CrtFilters {
+    pub fn new() -> Self {
+        CrtFilters::default()
+    }
+
+    pub fn is_enabled(&self, filter: CrtFilter) -> bool {
+        self.enabled & filter.mask() != 0
+    }
+
+    /// Returns `true` if any filter is enabled.
+    pub fn is_active(&self) -> bool {
+        self.enabled != 0
+    }
+
+    /// Enables or disables the filter.
+    pub fn set_enabled(&mut self, filter: CrtFilter, enabled: bool) {
+        if enabled {
+            self.enabled |= filter.mask();
+        }
+        else {
+            self.enabled &= !filter.mask();
+            if filter == CrtFilter::Ghosting {
+                self.prev = Vec::new();
+            }
+        }
+    }
+
+    /// Toggles the filter, returns `true` if the filter is now enabled.
+    pub fn toggle(&mut self, filter: CrtFilter) -> bool {
+        let enabled = !self.is_enabled(filter);
+        self.set_enabled(filter, enabled);
+        enabled
+    }
+
+    /// Returns the names of the enabled filters.
+    pub fn enabled_names(&self) -> impl Iterator<Item=&'static str> + '_ {
+        CrtFilter::ALL.iter().filter(move |&&f| self.is_enabled(f)).map(|f| f.name())
+    }
+
+    /// Applies the enabled filters to the frame of `width` pixels wide lines.
+    pub fn apply(&mut self, pixels: &mut [u32], width: usize) {
+        if !self.is_active() || width == 0 {
+            return
+        }
+        if self.is_enabled(CrtFilter::PalBleed) {
+            for line in pixels.chunks_exact_mut(width) {
+                self.pal_bleed(line);
+            }
+        }
+        if self.is_enabled(CrtFilter::HorizontalBlur) {
+            for line in pixels.chunks_exact_mut(width) {
+                self.horizontal_blur(line);
+            }
+        }
+        if self.is_enabled(CrtFilter::Ghosting) {
+            self.ghosting(pixels);
+        }
+        if self.is_enabled(CrtFilter::Scanlines) {
+            for line in pixels.chunks_exact_mut(width).skip(1).step_by(2) {
+                for pixel in line.iter_mut() {
+                    // 3/4 of the brightness
+                    *pixel = (*pixel >> 1 & 0x7F7F7F) + (*pixel >> 2 & 0x3F3F3F);
+                }
+            }
+        }
+    }
+
+    // each channel is (left + 2 * center + right) / 4
+    fn horizontal_blur(&mut self, line: &mut [u32]) {
+        self.line.clear();
+        self.line.extend_from_slice(line);
+        let last = line.len() - 1;
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let left = self.line[x.saturating_sub(1)];
+            let right = self.line[(x + 1).min(last)];
+            *pixel = map_channels3(left, self.line[x], right, |l, c, r| (l + 2 * c + r + 2) / 4);
+        }
+    }
+
+    // the chroma is averaged over the neighbouring pixels, the luma is kept
+    fn pal_bleed(&mut self, line: &mut [u32]) {
+        self.line.clear();
+        self.line.extend_from_slice(line);
+        self.chroma.clear();
+        self.chroma.extend(line.iter().map(|&pixel| {
+            let (_, u, v) = rgb_to_yuv(pixel);
+            (u, v)
+        }));
+        let len = line.len();
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let start = x.saturating_sub(BLEED_RADIUS);
+            let end = (x + BLEED_RADIUS + 1).min(len);
+            let (su, sv) = self.chroma[start..end].iter()
+                               .fold((0, 0), |(su, sv), &(u, v)| (su + u, sv + v));
+            let count = (end - start) as i32;
+            let (y, _, _) = rgb_to_yuv(self.line[x]);
+            *pixel = yuv_to_rgb(y, su / count, sv / count);
+        }
+    }
+
+    // each channel fades out from the previous frame to at most 5/8 of its brightness
+    fn ghosting(&mut self, pixels: &mut [u32]) {
+        if self.prev.len() != pixels.len() {
+            self.prev.clear();
+            self.prev.extend_from_slice(pixels);
+            return
+        }
+        for (pixel, prev) in pixels.iter_mut().zip(self.prev.iter_mut()) {
+            *pixel = map_channels3(*pixel, *prev, 0, |c, p, _| c.max(p * 5 / 8));
+            *prev = *pixel;
+        }
+    }
+}
+
+/// Averages each frame with the previous one, so the effects alternating
+/// the content of the frames are seen as the mix of the colours instead of flicker.
+#[derive(Debug, Clone, Default)]
+pub struct FrameBlender {
+    prev: Vec<u32>,
+    // the current frame before blending
+    current: Vec<u32>
+}
+
+impl FrameBlender {
+    pub fn new() -> Self {
+        FrameBlender::default()
+    }
+
+    /// Blends the XRGB frame with the previous one passed to this method.
+    pub fn blend(&mut self, pixels: &mut [u32]) {
+        self.current.clear();
+        self.current.extend_from_slice(pixels);
+        if self.prev.len() == pixels.len() {
+            for (pixel, &prev) in pixels.iter_mut().zip(self.prev.iter()) {
+                // the average of each channel without overflowing into the other ones
+                *pixel = (*pixel & prev) + ((*pixel ^ prev) >> 1 & 0x7F7F7F);
+            }
+        }
+        core::mem::swap(&mut self.prev, &mut self.current);
+    }
+}
+
+fn map_channels3<F: Fn(u32, u32, u32) -> u32>(a: u32, b: u32, c: u32, f: F) -> u32 {
+    let mut res = 0;
+    for shift in [16, 8, 0] {
+        let channel = f(a >> shift & 0xFF, b >> shift & 0xFF, c >> shift & 0xFF);
+        res |= channel.min(0xFF) << shift;
+    }
+    res
+}
+
+// the full range BT.601 YUV scaled by 256
+fn rgb_to_yuv(pixel: u32) -> (i32, i32, i32) {
+    let (r, g, b) = ((pixel >> 16 & 0xFF) as i32, (pixel >> 8 & 0xFF) as i32, (pixel & 0xFF) as i32);
+    let y = 77 * r + 150 * g + 29 * b;
+    let u = -43 * r - 85 * g + 128 * b;
+    let v = 128 * r - 107 * g - 21 * b;
+    (y, u, v)
+}
+
+fn yuv_to_rgb(y: i32, u: i32, v: i32) -> u32 {
+    let r = (y + 359 * v / 256) >> 8;
+    let g = (y - (88 * u + 183 * v) / 256) >> 8;
+    let b = (y + 454 * u / 256) >> 8;
+    (r.clamp(0, 255) as u32) << 16 | (g.clamp(0, 255) as u32) << 8 | b.clamp(0, 255) as u32
+}
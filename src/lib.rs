@@ -1,8 +1,28 @@
 use std::path::PathBuf;
 
+pub mod archive;
+pub mod tapefile;
+pub mod pzx;
+pub mod pulse_stream;
+pub mod tap_builder;
+pub mod capture;
+pub mod palette;
+pub mod settings;
+pub mod filters;
+pub mod scaler;
+pub mod ulaplus;
+pub mod osd;
+pub mod script;
+pub mod ay_log;
+pub mod mixer;
+pub mod audio_monitor;
+pub mod pacing;
+pub mod turbosound;
+pub mod save_monitor;
+
 pub fn open_tape_dialog() -> Option<PathBuf> {
     rfd::FileDialog::new()
-        .add_filter("TAPE", &["tap"])
+        .add_filter("TAPE", &["tap", "zip"])
         .set_title("Open TAP file")
         .pick_file()
 }
@@ -14,6 +34,26 @@ pub fn save_tape_dialog() -> Option<PathBuf> {
         .save_file()
 }
 
+pub fn save_pzx_dialog() -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter("PZX", &["pzx"])
+        .set_title("Record raw pulses to a PZX file")
+        .save_file()
+}
+
+pub fn open_palette_dialog() -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter("Palette", &["pal", "txt"])
+        .set_title("Open a palette file")
+        .pick_file()
+}
+
+pub fn save_tape_dir_dialog() -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .set_title("Select a directory for the recorded TAP files")
+        .pick_folder()
+}
+
 #[cfg(any(
     target_os = "linux",
     target_os = "freebsd",
@@ -107,3 +147,28 @@ macro_rules! measure_ticks {
         }
     };
 }
+
+#[macro_export]
+macro_rules! measure_cost_start {
+    ($cost:ident) => {
+        let mut $cost = (std::time::Instant::now(), std::time::Duration::ZERO, 0u32);
+    };
+}
+
+#[macro_export]
+macro_rules! measure_cost {
+    ($cost:ident, $name:expr, $body:expr) => {
+        {
+            const SECOND: std::time::Duration = std::time::Duration::from_secs(1);
+            let start = std::time::Instant::now();
+            let res = $body;
+            $cost.1 += start.elapsed();
+            $cost.2 += 1;
+            if $cost.0.elapsed() >= SECOND {
+                println!("{} per frame: {:?}", $name, $cost.1 / $cost.2);
+                $cost = (std::time::Instant::now(), std::time::Duration::ZERO, 0);
+            }
+            res
+        }
+    };
+}
@@ -0,0 +1,282 @@
+//! The mixing of the emulated audio: the AY stereo layout, the stereo separation,
+//! the master volume and the levels of the audio sources.
+//!
+//! Each [Source] is rendered into its own left, right and centre BLEP channels,
+//! so the sources can be mixed at different levels.
+use core::fmt;
+use core::str::FromStr;
+
+/// The left output channel index of each source.
+pub const LEFT_CHANNEL: usize = 0;
+/// The right output channel index of each source.
+pub const RIGHT_CHANNEL: usize = 1;
+/// The centre output channel index of each source, mixed into both the left and the right.
+pub const CENTER_CHANNEL: usize = 2;
+/// The number of the output channels of each source.
+pub const SOURCE_CHANNELS: usize = 3;
+/// The number of the BLEP channels of all the sources.
+pub const BLEP_CHANNELS: usize = Source::ALL.len() * SOURCE_CHANNELS;
+
+// the level of the centre channel mixed into the left and the right
+const CENTER_LEVEL: f32 = 0.8;
+
+/// The sources of the emulated audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The AY-3-891x sound generator.
+    Ay,
+    /// The beeper: the EAR OUT line and the MIC OUT line while the tape is audible.
+    Beeper,
+    /// The tape noise heard from the EAR IN line while the tape is audible.
+    Tape
+}
+
+impl Source {
+    pub const ALL: [Source; 3] = [
+        Source::Ay,
+        Source::Beeper,
+        Source::Tape
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Ay => "AY",
+            Source::Beeper => "Beeper",
+            Source::Tape => "Tape"
+        }
+    }
+
+    /// Returns the BLEP channel index of the source's output `channel`.
+    pub fn channel(self, channel: usize) -> usize {
+        self as usize * SOURCE_CHANNELS + channel
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How the AY channels A, B and C are placed in the stereo image.
+///
+/// The letters name the channels placed on the left, in the centre and on the right.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AyStereo {
+    Abc,
+    #[default]
+    Acb,
+    Bac,
+    Mono
+}
+
+impl AyStereo {
+    pub const ALL: [AyStereo; 4] = [
+        AyStereo::Abc,
+        AyStereo::Acb,
+        AyStereo::Bac,
+        AyStereo::Mono
+    ];
+
+    /// Returns the output channel indices of the AY channels A, B and C.
+    pub fn channels(self) -> [usize; 3] {
+        match self {
+            AyStereo::Abc => [LEFT_CHANNEL, CENTER_CHANNEL, RIGHT_CHANNEL],
+            AyStereo::Acb => [LEFT_CHANNEL, RIGHT_CHANNEL, CENTER_CHANNEL],
+            AyStereo::Bac => [CENTER_CHANNEL, LEFT_CHANNEL, RIGHT_CHANNEL],
+            AyStereo::Mono => [CENTER_CHANNEL, CENTER_CHANNEL, CENTER_CHANNEL]
+        }
+    }
+
+    /// Returns the following layout, wrapping around to the first one.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&s| s == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+impl fmt::Display for AyStereo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AyStereo::Abc => "ABC",
+            AyStereo::Acb => "ACB",
+            AyStereo::Bac => "BAC",
+            AyStereo::Mono => "mono"
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAyStereoError;
+
+impl fmt::Display for ParseAyStereoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unknown AY stereo layout, choose from: ABC|ACB|BAC|mono")
+    }
+}
+
+impl std::error::Error for ParseAyStereoError {}
+
+impl FromStr for AyStereo {
+    type Err = ParseAyStereoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AyStereo::ALL.iter().copied()
+                     .find(|stereo| stereo.to_string().eq_ignore_ascii_case(s))
+                     .ok_or(ParseAyStereoError)
+    }
+}
+
+/// The step of the stereo separation adjustment.
+pub const SEPARATION_STEP: f32 = 0.1;
+/// The step of the volume and the source levels adjustment.
+pub const LEVEL_STEP: f32 = 0.1;
+
+/// The audio mixing options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mixer {
+    pub ay_stereo: AyStereo,
+    // from 0.0 for mono to 1.0 for the full separation
+    separation: f32,
+    // the master volume from 0.0 to 1.0
+    volume: f32,
+    muted: bool,
+    // the level of each source from 0.0 to 1.0
+    levels: [f32; Source::ALL.len()]
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Mixer {
+            ay_stereo: AyStereo::default(),
+            separation: 1.0,
+            volume: 1.0,
+            muted: false,
+            levels: [1.0; Source::ALL.len()]
+        }
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Mixer::default()
+    }
+
+    pub fn separation(&self) -> f32 {
+        self.separation
+    }
+
+    /// Sets the stereo separation, clamped to the range `0.0..=1.0`.
+    pub fn set_separation(&mut self, separation: f32) {
+        self.separation = clamp_level(separation);
+    }
+
+    /// Changes the stereo separation by `steps` of [SEPARATION_STEP].
+    pub fn adjust_separation(&mut self, steps: i32) {
+        self.set_separation(step_level(self.separation, steps, SEPARATION_STEP));
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Sets the master volume, clamped to the range `0.0..=1.0`.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = clamp_level(volume);
+    }
+
+    /// Changes the master volume by `steps` of [LEVEL_STEP].
+    pub fn adjust_volume(&mut self, steps: i32) {
+        self.set_volume(step_level(self.volume, steps, LEVEL_STEP));
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Toggles the mute, returns `true` if the audio is now muted.
+    pub fn toggle_mute(&mut self) -> bool {
+        self.muted = !self.muted;
+        self.muted
+    }
+
+    pub fn level(&self, source: Source) -> f32 {
+        self.levels[source as usize]
+    }
+
+    /// Sets the level of the `source`, clamped to the range `0.0..=1.0`.
+    pub fn set_level(&mut self, source: Source, level: f32) {
+        self.levels[source as usize] = clamp_level(level);
+    }
+
+    /// Changes the level of the `source` by `steps` of [LEVEL_STEP].
+    pub fn adjust_level(&mut self, source: Source, steps: i32) {
+        self.set_level(source, step_level(self.level(source), steps, LEVEL_STEP));
+    }
+
+    /// Returns the BLEP channel indices of the AY channels A, B and C.
+    pub fn ay_channels(&self) -> [usize; 3] {
+        self.ay_stereo.channels().map(|channel| Source::Ay.channel(channel))
+    }
+
+    /// Returns the gains of the BLEP channels.
+    pub fn gains(&self) -> Gains {
+        // the stereo separation
+        let own = (1.0 + self.separation) * 0.5;
+        let other = 1.0 - own;
+        let mut gains = [(0.0, 0.0); BLEP_CHANNELS];
+        if self.muted {
+            return Gains(gains)
+        }
+        for source in Source::ALL {
+            let level = self.volume * self.level(source);
+            gains[source.channel(LEFT_CHANNEL)] = (own * level, other * level);
+            gains[source.channel(RIGHT_CHANNEL)] = (other * level, own * level);
+            gains[source.channel(CENTER_CHANNEL)] = (CENTER_LEVEL * level, CENTER_LEVEL * level);
+        }
+        Gains(gains)
+    }
+}
+
+impl fmt::Display for Mixer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.muted {
+            f.write_str("Muted")?;
+        }
+        else {
+            write!(f, "Vol {:.0}%", self.volume * 100.0)?;
+        }
+        for source in Source::ALL {
+            write!(f, " {} {:.0}%", source, self.level(source) * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// The left and the right gain of each BLEP channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gains([(f32, f32); BLEP_CHANNELS]);
+
+impl Gains {
+    /// Mixes the samples of the BLEP channels into the left and the right sample.
+    #[inline]
+    pub fn mix(&self, samples: &[f32; BLEP_CHANNELS]) -> (f32, f32) {
+        samples.iter().zip(self.0.iter())
+               .fold((0.0, 0.0), |(left, right), (&sample, &(lgain, rgain))| {
+                   (left + sample * lgain, right + sample * rgain)
+               })
+    }
+}
+
+fn clamp_level(level: f32) -> f32 {
+    if level.is_nan() { 1.0 } else { level.clamp(0.0, 1.0) }
+}
+
+// rounded, so the steps won't accumulate the errors
+fn step_level(level: f32, steps: i32, step: f32) -> f32 {
+    ((level / step).round() + steps as f32) * step
+}
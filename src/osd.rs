@@ -0,0 +1,276 @@
+//! The on-screen display drawn with the ZX Spectrum ROM font.
+//!
+//! The OSD shows the status lines in the top left corner, the FPS counter in
+//! the top right corner and the transient messages, that fade out after a while,
+//! in the bottom left corner.
+//!
+//! The messages are collected from the log records by the [OsdLogger].
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{Log, Level, LevelFilter, Metadata, Record, SetLoggerError};
+
+/// The size of the ROM font: 96 characters from `' '` to `'©'`, 8 bytes each.
+pub const FONT_SIZE: usize = 96 * 8;
+/// The address of the font in the 48k ROM.
+pub const ROM_FONT_ADDRESS: usize = 0x3D00;
+
+// how long the messages are shown
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+// how long the messages fade out at the end
+const MESSAGE_FADE: Duration = Duration::from_secs(1);
+// how many messages are shown at once
+const MAX_MESSAGES: usize = 4;
+const MARGIN: usize = 4;
+const LINE_HEIGHT: usize = 10;
+const TEXT_COLOR: u32 = 0xFFFFFF;
+
+/// The queue of the messages shared between the logger and the OSD.
+#[derive(Debug, Clone, Default)]
+pub struct MessageQueue(Arc<Mutex<Vec<String>>>);
+
+impl MessageQueue {
+    pub fn new() -> Self {
+        MessageQueue::default()
+    }
+
+    /// Adds the message, dropping the oldest one if nobody takes them.
+    pub fn push(&self, message: String) {
+        if let Ok(mut messages) = self.0.lock() {
+            if messages.len() >= MAX_MESSAGES {
+                messages.remove(0);
+            }
+            messages.push(message);
+        }
+    }
+
+    /// Takes all the messages from the queue.
+    pub fn take(&self) -> Vec<String> {
+        self.0.lock().map(|mut messages| core::mem::take(&mut *messages)).unwrap_or_default()
+    }
+}
+
+/// The logger forwarding the log records to the `inner` logger and queueing
+/// them as the OSD messages.
+///
+/// Only the records of the `target` module with the level of `Info` or more severe
+/// and the warnings and errors of any module are queued.
+pub struct OsdLogger<L> {
+    inner: L,
+    target: &'static str,
+    queue: MessageQueue
+}
+
+impl<L: Log> OsdLogger<L> {
+    pub fn new(inner: L, target: &'static str, queue: MessageQueue) -> Self {
+        OsdLogger { inner, target, queue }
+    }
+
+    /// Installs the logger with the maximum `level` and returns the queue of the messages.
+    pub fn init(inner: L, level: LevelFilter, target: &'static str) -> Result<MessageQueue, SetLoggerError>
+        where L: 'static
+    {
+        let queue = MessageQueue::new();
+        let logger = Box::leak(Box::new(OsdLogger::new(inner, target, queue.clone())));
+        log::set_logger(logger)?;
+        log::set_max_level(level);
+        Ok(queue)
+    }
+}
+
+impl<L: Log> Log for OsdLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return
+        }
+        self.inner.log(record);
+        let level = record.level();
+        if level <= Level::Warn || (level <= Level::Info && record.target() == self.target) {
+            self.queue.push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// The on-screen display.
+pub struct Osd {
+    enabled: bool,
+    font: Vec<u8>,
+    status: Vec<String>,
+    messages: VecDeque<(String, Instant)>,
+    queue: MessageQueue,
+    // the number of frames counted since the start of the FPS measurement
+    frames: u32,
+    fps_start: Instant,
+    fps: f32,
+    buffer: Vec<u32>
+}
+
+impl Osd {
+    /// Creates the OSD with the `font` in the ZX Spectrum ROM format, showing
+    /// the messages from the `queue`.
+    pub fn new(font: &[u8], queue: MessageQueue) -> Self {
+        let mut font = font.to_vec();
+        font.resize(FONT_SIZE, 0);
+        Osd {
+            enabled: true,
+            font,
+            status: Vec::new(),
+            messages: VecDeque::new(),
+            queue,
+            frames: 0,
+            fps_start: Instant::now(),
+            fps: 0.0,
+            buffer: Vec::new()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Replaces the status lines.
+    pub fn set_status<I: IntoIterator<Item=String>>(&mut self, lines: I) {
+        self.status.clear();
+        self.status.extend(lines);
+    }
+
+    /// Adds the transient message.
+    pub fn push_message<S: Into<String>>(&mut self, message: S) {
+        self.messages.push_back((message.into(), Instant::now()));
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+
+    /// Counts the displayed frame and returns the XRGB `frame` with the OSD drawn
+    /// over it or the `frame` itself if the OSD is disabled.
+    pub fn overlay<'a>(&'a mut self, frame: &'a [u32], width: usize, height: usize) -> &'a [u32] {
+        self.count_frame();
+        for message in self.queue.take() {
+            self.push_message(message);
+        }
+        let now = Instant::now();
+        while matches!(self.messages.front(), Some((_, time)) if now.duration_since(*time) >= MESSAGE_DURATION) {
+            self.messages.pop_front();
+        }
+        if !self.enabled {
+            return frame
+        }
+        let mut buffer = core::mem::take(&mut self.buffer);
+        buffer.clear();
+        buffer.extend_from_slice(frame);
+
+        for (index, line) in self.status.iter().enumerate() {
+            self.draw_text(&mut buffer, width, height, MARGIN, MARGIN + index * LINE_HEIGHT, line, 256);
+        }
+        let fps = format!("{:.1} FPS", self.fps);
+        let x = width.saturating_sub(MARGIN + fps.len() * 8);
+        self.draw_text(&mut buffer, width, height, x, MARGIN, &fps, 256);
+
+        let mut y = height.saturating_sub(MARGIN + LINE_HEIGHT * self.messages.len());
+        for (message, time) in self.messages.iter() {
+            let left = MESSAGE_DURATION.saturating_sub(now.duration_since(*time));
+            let alpha = if left < MESSAGE_FADE {
+                (left.as_millis() * 256 / MESSAGE_FADE.as_millis()) as u32
+            }
+            else {
+                256
+            };
+            self.draw_text(&mut buffer, width, height, MARGIN, y, message, alpha);
+            y += LINE_HEIGHT;
+        }
+        self.buffer = buffer;
+        &self.buffer
+    }
+
+    fn count_frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.fps_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f32 / elapsed.as_secs_f32();
+            self.frames = 0;
+            self.fps_start = Instant::now();
+        }
+    }
+
+    // draws the text on the darkened background, `alpha` is the opacity in the range 0..=256
+    #[allow(clippy::too_many_arguments)]
+    fn draw_text(
+            &self,
+            buffer: &mut [u32],
+            width: usize,
+            height: usize,
+            x: usize,
+            y: usize,
+            text: &str,
+            alpha: u32
+        )
+    {
+        if x >= width || y >= height {
+            return
+        }
+        let x0 = x.saturating_sub(1);
+        let text_width = (text.chars().count() * 8 + 2).min(width - x0);
+        for line in buffer.chunks_exact_mut(width).skip(y.saturating_sub(1)).take(LINE_HEIGHT) {
+            for pixel in line[x0..x0 + text_width].iter_mut() {
+                // the half of the brightness
+                let dark = *pixel >> 1 & 0x7F7F7F;
+                *pixel = blend(*pixel, dark, alpha);
+            }
+        }
+        for (index, ch) in text.chars().enumerate() {
+            let cx = x + index * 8;
+            if cx + 8 > width {
+                break
+            }
+            let glyph = self.glyph(ch);
+            for (row, &bits) in glyph.iter().enumerate() {
+                let line = match buffer.get_mut((y + row) * width..(y + row + 1) * width) {
+                    Some(line) if y + row < height => line,
+                    _ => break
+                };
+                for (bit, pixel) in line[cx..cx + 8].iter_mut().enumerate() {
+                    if bits & (0x80 >> bit) != 0 {
+                        *pixel = blend(*pixel, TEXT_COLOR, alpha);
+                    }
+                }
+            }
+        }
+    }
+
+    fn glyph(&self, ch: char) -> &[u8] {
+        let code = match ch {
+            ' '..='~' => ch as usize,
+            '£' => 0x60,
+            '©' => 0x7F,
+            _ => '?' as usize
+        };
+        let offset = (code - 0x20) * 8;
+        &self.font[offset..offset + 8]
+    }
+}
+
+// blends each channel of `b` over `a` with the opacity in the range 0..=256
+fn blend(a: u32, b: u32, alpha: u32) -> u32 {
+    if alpha >= 256 {
+        return b
+    }
+    let mut res = 0;
+    for shift in [16, 8, 0] {
+        let (ca, cb) = (a >> shift & 0xFF, b >> shift & 0xFF);
+        res |= ((ca * (256 - alpha) + cb * alpha) >> 8) << shift;
+    }
+    res
+}
@@ -0,0 +1,74 @@
+//! The pacing of the emulated frames by the audio output.
+//!
+//! When the audio paces the frames, each frame is emulated as soon as the audio carousel
+//! takes the previous one, so the emulation follows the clock of the audio device instead
+//! of the system timer. The device clock drifts from the system clock, so the [AudioPacer]
+//! slightly adjusts the rate of the emulated clock against the audio sample rate, so more
+//! or less samples are rendered per frame and the emulation keeps its real-time speed.
+use std::time::{Duration, Instant};
+
+/// The maximum adjustment of the clock rate, about 9 cents of the pitch.
+pub const MAX_RATIO_ADJUST: f64 = 0.005;
+/// How long it should take to correct the drift.
+pub const CORRECTION_SECS: f64 = 2.0;
+/// The drift after which the pacing starts over, e.g. after the emulation stalled.
+pub const MAX_DRIFT_SECS: f64 = 0.25;
+// the weight of the current drift in its moving average, smoothing the jitter of the frames
+const DRIFT_SMOOTHING: f64 = 0.05;
+
+/// Tracks the drift of the frames paced by the audio output against the system clock.
+#[derive(Debug, Clone)]
+pub struct AudioPacer {
+    start: Option<Instant>,
+    // the duration of the frames sent since the start
+    emulated: Duration,
+    // the smoothed drift in seconds, positive if the emulation is behind the system clock
+    drift: f64,
+    ratio: f64
+}
+
+impl Default for AudioPacer {
+    fn default() -> Self {
+        AudioPacer { start: None, emulated: Duration::ZERO, drift: 0.0, ratio: 1.0 }
+    }
+}
+
+impl AudioPacer {
+    pub fn new() -> Self {
+        AudioPacer::default()
+    }
+
+    /// Starts over with the next frame, e.g. when the audio is paused.
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.emulated = Duration::ZERO;
+        self.drift = 0.0;
+    }
+
+    /// Returns the ratio the emulated clock rate should be multiplied by, greater
+    /// than 1.0 when the emulation should speed up.
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Counts the frame lasting `frame_duration` sent at `now` and returns the updated ratio.
+    pub fn frame_sent(&mut self, now: Instant, frame_duration: Duration) -> f64 {
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                self.start = Some(now);
+                return self.ratio
+            }
+        };
+        self.emulated += frame_duration;
+        let drift = now.saturating_duration_since(start).as_secs_f64() - self.emulated.as_secs_f64();
+        if drift.abs() > MAX_DRIFT_SECS {
+            self.reset();
+            self.start = Some(now);
+            return self.ratio
+        }
+        self.drift += (drift - self.drift) * DRIFT_SMOOTHING;
+        self.ratio = 1.0 + (self.drift / CORRECTION_SECS).clamp(-MAX_RATIO_ADJUST, MAX_RATIO_ADJUST);
+        self.ratio
+    }
+}
@@ -0,0 +1,170 @@
+//! Colour palettes of the 16 ZX Spectrum colours.
+//!
+//! Colours are XRGB `u32` values in the order of the ZX Spectrum colour indices:
+//! the 8 normal colours followed by the 8 bright ones.
+//!
+//! The palette file is a text file with 16 colours in the `RRGGBB` hexadecimal
+//! notation, optionally prefixed with `#` or `0x`, separated by white space or new
+//! lines. Anything after `;` up to the end of the line is ignored.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The number of colours in the palette.
+pub const PALETTE_SIZE: usize = 16;
+
+/// The colours of the palette.
+pub type Colors = [u32; PALETTE_SIZE];
+
+/// The names of the built-in palettes, the first one is the default.
+pub const BUILTIN_NAMES: [&str; 4] = [
+    "Spectrum",
+    "Greyscale",
+    "Green phosphor",
+    "Amber"
+];
+
+const GREEN_PHOSPHOR: u32 = 0x33FF66;
+const AMBER: u32 = 0xFFB000;
+
+/// A named palette.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorPalette {
+    pub name: String,
+    pub colors: Colors
+}
+
+impl ColorPalette {
+    pub fn new<S: Into<String>>(name: S, colors: Colors) -> Self {
+        ColorPalette { name: name.into(), colors }
+    }
+
+    /// Reads the palette from the file, the palette is named after the file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let colors = parse_colors(&fs::read_to_string(path)?)?;
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("Custom");
+        Ok(ColorPalette::new(name, colors))
+    }
+
+    /// Creates a monochrome palette with the brightness of the `base` colours
+    /// applied to the `tint` colour.
+    pub fn monochrome<S: Into<String>>(name: S, base: &Colors, tint: u32) -> Self {
+        let mut colors = [0; PALETTE_SIZE];
+        for (color, &base) in colors.iter_mut().zip(base.iter()) {
+            let luma = luma(base);
+            *color = map_channels(tint, |c| (c as u32 * luma / 255) as u8);
+        }
+        ColorPalette::new(name, colors)
+    }
+}
+
+/// The list of the palettes to choose from.
+#[derive(Debug, Clone)]
+pub struct Palettes {
+    palettes: Vec<ColorPalette>,
+    selected: usize
+}
+
+impl Palettes {
+    /// Creates the built-in palettes, the `default` colours are used for the default
+    /// palette and as the base for the monochrome ones.
+    pub fn new(default: Colors) -> Self {
+        let palettes = vec![
+            ColorPalette::new(BUILTIN_NAMES[0], default),
+            ColorPalette::monochrome(BUILTIN_NAMES[1], &default, 0xFFFFFF),
+            ColorPalette::monochrome(BUILTIN_NAMES[2], &default, GREEN_PHOSPHOR),
+            ColorPalette::monochrome(BUILTIN_NAMES[3], &default, AMBER)
+        ];
+        Palettes { palettes, selected: 0 }
+    }
+
+    pub fn current(&self) -> &ColorPalette {
+        &self.palettes[self.selected]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&ColorPalette> {
+        self.palettes.iter()
+    }
+
+    /// Selects the palette at `index`, returns `None` if there is no such palette.
+    pub fn select(&mut self, index: usize) -> Option<&ColorPalette> {
+        if index < self.palettes.len() {
+            self.selected = index;
+            Some(self.current())
+        }
+        else {
+            None
+        }
+    }
+
+    /// Selects the palette by its name, ignoring the case.
+    pub fn select_by_name(&mut self, name: &str) -> Option<&ColorPalette> {
+        let index = self.palettes.iter().position(|p| p.name.eq_ignore_ascii_case(name))?;
+        self.select(index)
+    }
+
+    /// Selects the next palette, wrapping around to the first one.
+    pub fn select_next(&mut self) -> &ColorPalette {
+        self.selected = (self.selected + 1) % self.palettes.len();
+        self.current()
+    }
+
+    /// Adds and selects the palette, a palette with the same name is replaced.
+    pub fn add(&mut self, palette: ColorPalette) -> &ColorPalette {
+        match self.palettes.iter().position(|p| p.name == palette.name) {
+            Some(index) => {
+                self.palettes[index] = palette;
+                self.selected = index;
+            }
+            None => {
+                self.palettes.push(palette);
+                self.selected = self.palettes.len() - 1;
+            }
+        }
+        self.current()
+    }
+}
+
+/// Parses exactly 16 colours in the palette file format.
+pub fn parse_colors(text: &str) -> io::Result<Colors> {
+    let mut colors = [0; PALETTE_SIZE];
+    let mut count = 0;
+    for word in text.lines().flat_map(|line| line.split(';').next().unwrap_or("").split_whitespace()) {
+        let hex = word.strip_prefix('#').or_else(|| word.strip_prefix("0x")).unwrap_or(word);
+        let color = match u32::from_str_radix(hex, 16) {
+            Ok(color) if hex.len() == 6 => color,
+            _ => return Err(invalid_data(format!("not a RRGGBB colour: \"{}\"", word)))
+        };
+        if count == PALETTE_SIZE {
+            return Err(invalid_data(format!("more than {} colours", PALETTE_SIZE)))
+        }
+        colors[count] = color;
+        count += 1;
+    }
+    if count != PALETTE_SIZE {
+        return Err(invalid_data(format!("expected {} colours, found: {}", PALETTE_SIZE, count)))
+    }
+    Ok(colors)
+}
+
+/// Replaces the palette indices in the frame with the palette colours.
+pub fn apply_palette(pixels: &mut [u32], colors: &Colors) {
+    for pixel in pixels.iter_mut() {
+        *pixel = colors[*pixel as usize & (PALETTE_SIZE - 1)];
+    }
+}
+
+// the perceived brightness of XRGB colour in the range 0..=255
+fn luma(color: u32) -> u32 {
+    let (r, g, b) = (color >> 16 & 0xFF, color >> 8 & 0xFF, color & 0xFF);
+    (299 * r + 587 * g + 114 * b) / 1000
+}
+
+fn map_channels<F: Fn(u8) -> u8>(color: u32, f: F) -> u32 {
+    (f((color >> 16) as u8) as u32) << 16 | (f((color >> 8) as u8) as u32) << 8 | f(color as u8) as u32
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,249 @@
+//! Pulse streams read from a named pipe, a file or the standard input.
+//!
+//! The stream is read by a background thread, so the emulator can take the pulses
+//! as they arrive without ever blocking.
+//!
+//! The text format consists of pulse lengths in T-states as decimal numbers separated
+//! by white space or new lines. Lines starting with `#` are ignored.
+//! The binary format consists of pulse lengths in T-states as 32-bit little-endian
+//! unsigned integers.
+//!
+//! Pulses of zero length are ignored.
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use log::error;
+
+/// The format of the pulse stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PulseFormat {
+    Text,
+    Binary
+}
+
+// how many pulses are sent to the emulator at once
+const PULSE_BATCH: usize = 256;
+
+/// The receiving end of the pulse stream.
+pub struct PulseStream {
+    receiver: Receiver<Vec<NonZeroU32>>,
+    pulses: VecDeque<NonZeroU32>,
+    ended: bool
+}
+
+impl PulseStream {
+    /// Opens the stream from the file or the named pipe at `path` or from the
+    /// standard input if `path` is `-`.
+    ///
+    /// The file is opened by the reading thread, as opening a named pipe blocks until
+    /// the pipe is opened for writing.
+    pub fn open<P: AsRef<Path>>(path: P, format: PulseFormat) -> Self {
+        let path = path.as_ref();
+        if path == Path::new("-") {
+            Self::new(io::stdin(), format)
+        }
+        else {
+            let path = path.to_path_buf();
+            Self::spawn(move || File::open(&path).map_err(|err| io::Error::new(
+                                    err.kind(), format!("{}: {}", path.display(), err))),
+                        format)
+        }
+    }
+
+    /// Spawns a thread reading the pulses from `reader`.
+    pub fn new<R: Read + Send + 'static>(reader: R, format: PulseFormat) -> Self {
+        Self::spawn(move || Ok(reader), format)
+    }
+
+    // spawns a thread reading the pulses from the reader returned by `open`
+    fn spawn<R, F>(open: F, format: PulseFormat) -> Self
+        where R: Read,
+              F: FnOnce() -> io::Result<R> + Send + 'static
+    {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let res = open().and_then(|reader| {
+                let reader = BufReader::new(reader);
+                match format {
+                    PulseFormat::Text => read_text_pulses(reader, |batch| sender.send(batch).is_ok()),
+                    PulseFormat::Binary => read_binary_pulses(reader, |batch| sender.send(batch).is_ok())
+                }
+            });
+            if let Err(err) = res {
+                error!("Error reading the pulse stream: {}", err);
+            }
+        });
+        PulseStream { receiver, pulses: VecDeque::new(), ended: false }
+    }
+
+    /// Returns `true` if the stream has ended and all of its pulses were taken.
+    pub fn is_ended(&self) -> bool {
+        self.ended && self.pulses.is_empty()
+    }
+
+    /// Returns `true` if there are some pulses waiting to be taken.
+    pub fn has_pulses(&mut self) -> bool {
+        self.receive();
+        !self.pulses.is_empty()
+    }
+
+    /// Returns an iterator taking the pulses that have already arrived.
+    ///
+    /// The pulses not consumed by the iterator are left for the next time.
+    pub fn pulses(&mut self) -> impl Iterator<Item=NonZeroU32> + '_ {
+        self.receive();
+        core::iter::from_fn(move || self.pulses.pop_front())
+    }
+
+    fn receive(&mut self) {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(batch) => self.pulses.extend(batch),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.ended = true;
+                    break
+                }
+            }
+        }
+    }
+}
+
+// returns `Ok` when the stream ends or the receiving end is gone
+fn read_text_pulses<R: BufRead, F: FnMut(Vec<NonZeroU32>) -> bool>(
+        reader: R,
+        mut send: F
+    ) -> io::Result<()>
+{
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim_start().starts_with('#') {
+            continue
+        }
+        let mut batch = Vec::new();
+        for word in line.split_whitespace() {
+            let pulse: u32 = word.parse().map_err(|_| io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("not a pulse length: \"{}\"", word)))?;
+            batch.extend(NonZeroU32::new(pulse));
+        }
+        if !batch.is_empty() && !send(batch) {
+            break
+        }
+    }
+    Ok(())
+}
+
+// returns `Ok` when the stream ends or the receiving end is gone
+fn read_binary_pulses<R: Read, F: FnMut(Vec<NonZeroU32>) -> bool>(
+        mut reader: R,
+        mut send: F
+    ) -> io::Result<()>
+{
+    let mut buf = [0u8; 4 * PULSE_BATCH];
+    let mut filled = 0;
+    loop {
+        let len = match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+        filled += len;
+        let complete = filled - filled % 4;
+        let batch: Vec<_> = buf[..complete].chunks_exact(4)
+            .filter_map(|b| NonZeroU32::new(u32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .collect();
+        buf.copy_within(complete..filled, 0);
+        filled -= complete;
+        if !batch.is_empty() && !send(batch) {
+            break
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::{Duration, Instant};
+
+    fn nonzero(pulses: &[u32]) -> Vec<NonZeroU32> {
+        pulses.iter().map(|&p| NonZeroU32::new(p).unwrap()).collect()
+    }
+
+    fn read_all(mut stream: PulseStream) -> Vec<NonZeroU32> {
+        let mut pulses = Vec::new();
+        let start = Instant::now();
+        while !stream.is_ended() {
+            assert!(start.elapsed() < Duration::from_secs(10), "the stream never ends");
+            pulses.extend(stream.pulses());
+            thread::sleep(Duration::from_millis(1));
+        }
+        pulses
+    }
+
+    // reads at most 3 bytes at once
+    struct Trickle<R>(R);
+
+    impl<R: Read> Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn text_pulses_are_read() {
+        let text = "# pilot\n2168 2168\n  # comment\n\n667\t735 0\n855\n";
+        let mut batches = Vec::new();
+        read_text_pulses(text.as_bytes(), |batch| { batches.push(batch); true }).unwrap();
+        assert_eq!(batches, vec![nonzero(&[2168, 2168]), nonzero(&[667, 735]), nonzero(&[855])]);
+    }
+
+    #[test]
+    fn invalid_text_is_an_error() {
+        let err = read_text_pulses(&b"100\n-5\n"[..], |_| true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reading_stops_when_receiver_is_gone() {
+        let mut sent = 0;
+        read_text_pulses(&b"1\n2\n3\n"[..], |_| { sent += 1; false }).unwrap();
+        assert_eq!(sent, 1);
+    }
+
+    #[test]
+    fn binary_pulses_are_read() {
+        let pulses = [2168u32, 0, 667, 735, 0x1234_5678];
+        let data: Vec<u8> = pulses.iter().flat_map(|p| p.to_le_bytes().to_vec()).collect();
+        let mut received = Vec::new();
+        // the pulses are split between the reads
+        read_binary_pulses(Trickle(&data[..]), |batch| { received.extend(batch); true }).unwrap();
+        assert_eq!(received, nonzero(&[2168, 667, 735, 0x1234_5678]));
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let pulses: Vec<u32> = (1..=1000).collect();
+        let text: String = pulses.iter().map(|p| format!("{}\n", p)).collect();
+        let stream = PulseStream::new(Cursor::new(text.into_bytes()), PulseFormat::Text);
+        assert_eq!(read_all(stream), nonzero(&pulses));
+
+        let data: Vec<u8> = pulses.iter().flat_map(|p| p.to_le_bytes().to_vec()).collect();
+        let stream = PulseStream::new(Cursor::new(data), PulseFormat::Binary);
+        assert_eq!(read_all(stream), nonzero(&pulses));
+    }
+
+    #[test]
+    fn missing_file_ends_stream() {
+        let stream = PulseStream::open("/nonexistent/pulses", PulseFormat::Text);
+        assert!(read_all(stream).is_empty());
+    }
+}
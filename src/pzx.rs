@@ -0,0 +1,210 @@
+//! A minimal writer of the PZX tape files containing raw pulse sequences.
+//!
+//! See: http://zxds.raxoft.cz/docs/pzx.txt
+use std::io::{self, Write};
+use std::num::NonZeroU32;
+
+const PZX_MAJOR: u8 = 1;
+const PZX_MINOR: u8 = 0;
+// the longest pulse that can be encoded
+const MAX_DURATION: u32 = 0x7FFF_FFFF;
+// the maximum pulse repeat count
+const MAX_COUNT: u16 = 0x7FFF;
+// pulses this long end the current PULS block
+const BLOCK_END_DURATION: u32 = 3_500_000;
+
+/// Writes pulse lengths, in T-states, as PZX `PULS` blocks.
+///
+/// The block is written out each time a pulse longer than about a second
+/// is encountered and when the writer is finished or dropped.
+pub struct PzxWriter<W: Write> {
+    writer: Option<W>,
+    block: Vec<u8>,
+    last: Option<(u32, u16)>
+}
+
+impl<W: Write> PzxWriter<W> {
+    /// Creates a new writer and writes the PZX header block with the `info` title.
+    pub fn new(mut writer: W, info: &str) -> io::Result<Self> {
+        let mut header = vec![PZX_MAJOR, PZX_MINOR];
+        header.extend_from_slice(info.as_bytes());
+        write_block(&mut writer, b"PZXT", &header)?;
+        Ok(PzxWriter { writer: Some(writer), block: Vec::new(), last: None })
+    }
+
+    /// Appends pulses from the iterator to the current block.
+    pub fn write_pulses<I: IntoIterator<Item=NonZeroU32>>(&mut self, pulses: I) -> io::Result<()> {
+        for pulse in pulses {
+            let mut duration = pulse.get();
+            // split pulses too long to be encoded with zero length pulses in between
+            while duration > MAX_DURATION {
+                self.push_pulse(MAX_DURATION);
+                self.push_pulse(0);
+                duration -= MAX_DURATION;
+            }
+            self.push_pulse(duration);
+            if duration >= BLOCK_END_DURATION {
+                self.end_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the current block out.
+    pub fn end_block(&mut self) -> io::Result<()> {
+        self.flush_repeated();
+        if let Some(writer) = self.writer.as_mut() {
+            if !self.block.is_empty() {
+                write_block(writer, b"PULS", &self.block)?;
+                self.block.clear();
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the current block out and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_block()?;
+        Ok(self.writer.take().unwrap())
+    }
+
+    fn push_pulse(&mut self, duration: u32) {
+        match self.last.as_mut() {
+            Some((last, count)) if *last == duration && *count < MAX_COUNT => {
+                *count += 1;
+                return
+            }
+            _ => {}
+        }
+        self.flush_repeated();
+        self.last = Some((duration, 1));
+    }
+
+    fn flush_repeated(&mut self) {
+        if let Some((duration, count)) = self.last.take() {
+            let block = &mut self.block;
+            // the count must precede long pulses, so the high word of the duration
+            // isn't mistaken for the count
+            if count > 1 || duration > 0xFFFF {
+                block.extend_from_slice(&(0x8000 | count).to_le_bytes());
+            }
+            if duration < 0x8000 {
+                block.extend_from_slice(&(duration as u16).to_le_bytes());
+            }
+            else {
+                block.extend_from_slice(&(0x8000 | (duration >> 16) as u16).to_le_bytes());
+                block.extend_from_slice(&(duration as u16).to_le_bytes());
+            }
+        }
+    }
+}
+
+impl<W: Write> Drop for PzxWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.end_block();
+    }
+}
+
+fn write_block<W: Write>(writer: &mut W, tag: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(tag)?;
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // splits the PZX file into the tagged blocks
+    fn blocks(mut data: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let mut tag = [0; 4];
+            tag.copy_from_slice(&data[0..4]);
+            let mut len = [0; 4];
+            len.copy_from_slice(&data[4..8]);
+            let len = u32::from_le_bytes(len) as usize;
+            blocks.push((tag, data[8..8 + len].to_vec()));
+            data = &data[8 + len..];
+        }
+        blocks
+    }
+
+    // decodes the PULS block as described in the PZX specification
+    fn decode_pulses(data: &[u8]) -> Vec<u32> {
+        let mut words = data.chunks_exact(2).map(|w| u16::from_le_bytes([w[0], w[1]]) as u32);
+        let mut pulses = Vec::new();
+        while let Some(mut word) = words.next() {
+            let mut count = 1;
+            if word > 0x8000 {
+                count = word & 0x7FFF;
+                word = words.next().unwrap();
+            }
+            let duration = if word >= 0x8000 {
+                (word & 0x7FFF) << 16 | words.next().unwrap()
+            }
+            else {
+                word
+            };
+            pulses.extend((0..count).map(|_| duration));
+        }
+        pulses
+    }
+
+    fn pulses(durations: &[u32]) -> impl Iterator<Item=NonZeroU32> + '_ {
+        durations.iter().map(|&d| NonZeroU32::new(d).unwrap())
+    }
+
+    #[test]
+    fn header_is_written() {
+        let data = PzxWriter::new(Vec::new(), "test").unwrap().finish().unwrap();
+        assert_eq!(blocks(&data), vec![(*b"PZXT", b"\x01\x00test".to_vec())]);
+    }
+
+    #[test]
+    fn pulses_round_trip() {
+        let durations = [2168, 2168, 2168, 667, 735, 855, 855, 1710, 0x8000, 0x12345, 1];
+        let mut writer = PzxWriter::new(Vec::new(), "").unwrap();
+        writer.write_pulses(pulses(&durations)).unwrap();
+        let data = writer.finish().unwrap();
+        let blocks = blocks(&data);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(&blocks[1].0, b"PULS");
+        // the repeated pulses take a single count word
+        assert_eq!(&blocks[1].1[0..4], &[0x03, 0x80, 0x78, 0x08]);
+        assert_eq!(decode_pulses(&blocks[1].1), durations);
+    }
+
+    #[test]
+    fn long_pulse_ends_block() {
+        let durations = [1000, BLOCK_END_DURATION, 2000, 2000];
+        let mut writer = PzxWriter::new(Vec::new(), "").unwrap();
+        writer.write_pulses(pulses(&durations)).unwrap();
+        let data = writer.finish().unwrap();
+        let blocks = blocks(&data);
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(decode_pulses(&blocks[1].1), [1000, BLOCK_END_DURATION]);
+        assert_eq!(decode_pulses(&blocks[2].1), [2000, 2000]);
+    }
+
+    #[test]
+    fn too_long_pulse_is_split() {
+        let mut writer = PzxWriter::new(Vec::new(), "").unwrap();
+        writer.write_pulses(pulses(&[MAX_DURATION + 5])).unwrap();
+        let data = writer.finish().unwrap();
+        let blocks = blocks(&data);
+        assert_eq!(decode_pulses(&blocks[1].1), [MAX_DURATION, 0, 5]);
+    }
+
+    #[test]
+    fn repeat_count_is_limited() {
+        let count = MAX_COUNT as usize + 5;
+        let mut writer = PzxWriter::new(Vec::new(), "").unwrap();
+        writer.write_pulses((0..count).map(|_| NonZeroU32::new(100).unwrap())).unwrap();
+        let data = writer.finish().unwrap();
+        let blocks = blocks(&data);
+        assert_eq!(&blocks[1].1, &[0xFF, 0xFF, 100, 0, 0x05, 0x80, 100, 0]);
+        assert_eq!(decode_pulses(&blocks[1].1).len(), count);
+    }
+}
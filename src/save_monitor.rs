@@ -0,0 +1,125 @@
+//! The detection of the saves the TAP decoder can't make sense of.
+//!
+//! The TAP decoder finishes a block only when the pulse following the last byte arrives,
+//! so a long standard block keeps the decoder busy until its very end, and the number
+//! of its pulses tells nothing on its own. A save is considered undecoded when:
+//!
+//! * the MIC OUT goes silent while the decoder is still in the middle of a block, so
+//!   the pulses don't look like the end of any block to the decoder,
+//! * or the decoder went back to idle having rejected more pulses than a pilot tone
+//!   with a few bytes, without producing any chunk.
+use core::num::NonZeroU32;
+
+/// How many frames of silence end the save, about a second.
+pub const SILENCE_FRAMES: u32 = 50;
+/// How many pulses rejected by the idle decoder are more than a pilot tone with a few bytes.
+pub const REJECTED_THRESHOLD: u32 = 10000;
+
+/// Monitors the MIC OUT pulses written to the TAP decoder frame by frame.
+#[derive(Debug, Clone, Default)]
+pub struct SaveMonitor {
+    // the pulses since the last decoded chunk
+    pulses: u32,
+    silent_frames: u32
+}
+
+impl SaveMonitor {
+    pub fn new() -> Self {
+        SaveMonitor::default()
+    }
+
+    /// Forgets the pulses counted so far.
+    pub fn reset(&mut self) {
+        self.pulses = 0;
+        self.silent_frames = 0;
+    }
+
+    /// Counts the frame with `pulse_count` pulses from which `chunks` TAP chunks were
+    /// decoded, `idle` tells if the decoder is between the blocks after the frame.
+    ///
+    /// Returns the number of the undecoded pulses once the save is considered undecoded.
+    pub fn frame(&mut self, chunks: usize, pulse_count: u32, idle: bool) -> Option<NonZeroU32> {
+        if chunks != 0 {
+            self.pulses = 0;
+        }
+        self.pulses = self.pulses.saturating_add(pulse_count);
+        if pulse_count != 0 {
+            self.silent_frames = 0;
+            return None
+        }
+        self.silent_frames = self.silent_frames.saturating_add(1);
+        if self.silent_frames != SILENCE_FRAMES {
+            return None
+        }
+        let pulses = core::mem::take(&mut self.pulses);
+        if !idle || pulses > REJECTED_THRESHOLD {
+            NonZeroU32::new(pulses)
+        }
+        else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the pulses of a standard block: the pilot tone, the sync pulses and 2 pulses per bit
+    fn block_pulses(data_len: u32) -> u32 {
+        8063 + 2 + data_len * 16
+    }
+
+    fn silence(monitor: &mut SaveMonitor, idle: bool) -> Option<NonZeroU32> {
+        (0..SILENCE_FRAMES * 2).fold(None, |res, _| res.or(monitor.frame(0, 0, idle)))
+    }
+
+    #[test]
+    fn long_standard_block_is_decoded() {
+        let mut monitor = SaveMonitor::new();
+        // a 48k block takes about 5 minutes, the decoder is busy all the time
+        let total = block_pulses(49152);
+        let per_frame = 2000;
+        for _ in 0..total / per_frame {
+            assert_eq!(monitor.frame(0, per_frame, false), None);
+        }
+        // the last pulses end the block
+        assert_eq!(monitor.frame(1, total % per_frame + 1, true), None);
+        assert_eq!(silence(&mut monitor, true), None);
+    }
+
+    #[test]
+    fn block_stuck_in_decoder_is_reported() {
+        let mut monitor = SaveMonitor::new();
+        for _ in 0..10 {
+            assert_eq!(monitor.frame(0, 500, false), None);
+        }
+        assert_eq!(silence(&mut monitor, false), NonZeroU32::new(5000));
+        // reported once
+        assert_eq!(silence(&mut monitor, false), None);
+    }
+
+    #[test]
+    fn rejected_pulses_are_reported() {
+        let mut monitor = SaveMonitor::new();
+        for _ in 0..30 {
+            assert_eq!(monitor.frame(0, 1000, true), None);
+        }
+        assert_eq!(silence(&mut monitor, true), NonZeroU32::new(30000));
+    }
+
+    #[test]
+    fn short_noise_is_ignored() {
+        let mut monitor = SaveMonitor::new();
+        assert_eq!(monitor.frame(0, 3, true), None);
+        assert_eq!(silence(&mut monitor, true), None);
+    }
+
+    #[test]
+    fn pulses_before_the_chunk_are_forgotten() {
+        let mut monitor = SaveMonitor::new();
+        assert_eq!(monitor.frame(0, 20000, true), None);
+        assert_eq!(monitor.frame(2, 100, true), None);
+        assert_eq!(silence(&mut monitor, true), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Window scaling options and the software resampling of the frame to the PAL pixel aspect.
+use core::fmt;
+use core::str::FromStr;
+
+/// The ratio of the width to the height of the ZX Spectrum pixel on a PAL display.
+///
+/// The pixels are clocked at 7 MHz, while the square pixels of PAL are clocked
+/// at 7.375 MHz.
+pub const PAL_PIXEL_ASPECT: f64 = 7.375 / 7.0;
+
+/// The integer scale of the window or the largest scale that fits the screen.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WindowScale {
+    X1,
+    #[default]
+    X2,
+    X4,
+    Fit
+}
+
+impl WindowScale {
+    pub const ALL: [WindowScale; 4] = [
+        WindowScale::X1,
+        WindowScale::X2,
+        WindowScale::X4,
+        WindowScale::Fit
+    ];
+
+    /// Returns the following scale, wrapping around to the first one.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&s| s == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+impl fmt::Display for WindowScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WindowScale::X1 => "x1",
+            WindowScale::X2 => "x2",
+            WindowScale::X4 => "x4",
+            WindowScale::Fit => "fit"
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWindowScaleError;
+
+impl fmt::Display for ParseWindowScaleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unknown window scale, choose from: 1|2|4|fit")
+    }
+}
+
+impl std::error::Error for ParseWindowScaleError {}
+
+impl FromStr for WindowScale {
+    type Err = ParseWindowScaleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_start_matches(['x', 'X']);
+        match s {
+            "1" => Ok(WindowScale::X1),
+            "2" => Ok(WindowScale::X2),
+            "4" => Ok(WindowScale::X4),
+            s if s.eq_ignore_ascii_case("fit") => Ok(WindowScale::Fit),
+            _ => Err(ParseWindowScaleError)
+        }
+    }
+}
+
+// the fixed point precision of the resampling weights
+const WEIGHT_BITS: u32 = 8;
+const WEIGHT_ONE: u32 = 1 << WEIGHT_BITS;
+
+/// Stretches the frame lines horizontally with the linear interpolation.
+#[derive(Debug, Clone)]
+pub struct AspectResampler {
+    src_width: usize,
+    dst_width: usize,
+    // the source pixel index and the weight of the following pixel for each target pixel
+    taps: Vec<(usize, u32)>,
+    output: Vec<u32>
+}
+
+impl AspectResampler {
+    /// Creates the resampler of `src_width` pixels wide lines to the width stretched
+    /// by the `aspect` ratio.
+    pub fn new(src_width: usize, aspect: f64) -> Self {
+        let dst_width = (src_width as f64 * aspect).round().max(1.0) as usize;
+        let step = src_width as f64 / dst_width as f64;
+        let taps = (0..dst_width).map(|x| {
+            // the centre of the target pixel in the source coordinates
+            let pos = ((x as f64 + 0.5) * step - 0.5).max(0.0);
+            let index = (pos as usize).min(src_width.saturating_sub(1));
+            let weight = ((pos - index as f64) * WEIGHT_ONE as f64).round() as u32;
+            (index, weight.min(WEIGHT_ONE))
+        }).collect();
+        AspectResampler { src_width, dst_width, taps, output: Vec::new() }
+    }
+
+    pub fn src_width(&self) -> usize {
+        self.src_width
+    }
+
+    pub fn dst_width(&self) -> usize {
+        self.dst_width
+    }
+
+    /// Resamples the XRGB frame and returns the stretched one.
+    pub fn resample(&mut self, pixels: &[u32]) -> &[u32] {
+        let lines = pixels.len() / self.src_width.max(1);
+        self.output.resize(lines * self.dst_width, 0);
+        for (src, dst) in pixels.chunks_exact(self.src_width)
+                                .zip(self.output.chunks_exact_mut(self.dst_width)) {
+            for (pixel, &(index, weight)) in dst.iter_mut().zip(self.taps.iter()) {
+                let a = src[index];
+                let b = src[(index + 1).min(self.src_width - 1)];
+                *pixel = if weight == 0 || a == b { a } else { lerp(a, b, weight) };
+            }
+        }
+        &self.output
+    }
+}
+
+fn lerp(a: u32, b: u32, weight: u32) -> u32 {
+    let mut res = 0;
+    for shift in [16, 8, 0] {
+        let (ca, cb) = (a >> shift & 0xFF, b >> shift & 0xFF);
+        let c = (ca * (WEIGHT_ONE - weight) + cb * weight + WEIGHT_ONE / 2) >> WEIGHT_BITS;
+        res |= c << shift;
+    }
+    res
+}
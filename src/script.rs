@@ -0,0 +1,233 @@
+//! The scripts driving the emulator without the user interaction.
+//!
+//! A script is a text file with one command per line, the text after `#` is ignored:
+//!
+//! ```text
+//! wait FRAMES            run the emulation for the number of frames
+//! press KEY...           press and hold the keys
+//! release KEY...         release the keys
+//! type TEXT              type the letters, digits and spaces
+//! screenshot FILE        save the frame as a PNG file
+//! screenshot-screen FILE save the frame without the border as a PNG file
+//! record-audio FILE      start recording the audio into a WAV file
+//! stop-audio             stop recording the audio
+//! model 16|48|128        change the model
+//! reset [soft]           reset the machine, hard by default
+//! nmi                    trigger the non-maskable interrupt
+//! tape FILE              insert the TAP file
+//! tape play|stop|rewind|next|prev|eject
+//! exit                   end the emulation
+//! ```
+//!
+//! The keys are named as the [Key] variants, e.g. `A`, `Key1`, `Enter`, `Space` or `LeftShift`.
+use core::num::NonZeroU32;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use minifb::Key;
+
+// how many frames each key is held and released while typing
+const TYPE_HOLD_FRAMES: u32 = 3;
+const TYPE_GAP_FRAMES: u32 = 3;
+
+/// The tape recorder commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapeCommand {
+    Insert(PathBuf),
+    Play,
+    Stop,
+    Rewind,
+    Next,
+    Prev,
+    Eject
+}
+
+/// A single script command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Wait(NonZeroU32),
+    Press(Vec<Key>),
+    Release(Vec<Key>),
+    Screenshot { path: PathBuf, no_border: bool },
+    RecordAudio(PathBuf),
+    StopAudio,
+    /// The model by its memory size in kilobytes.
+    Model(u32),
+    Reset { hard: bool },
+    Nmi,
+    Tape(TapeCommand),
+    Exit
+}
+
+/// The parsed script being executed.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    commands: VecDeque<Command>,
+    // the number of frames left until the next command
+    wait: u32
+}
+
+impl Script {
+    /// Parses the script text.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut commands = VecDeque::new();
+        for (index, line) in text.lines().enumerate() {
+            parse_line(line, &mut commands).map_err(|msg| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", index + 1, msg))
+            })?;
+        }
+        Ok(Script { commands, wait: 0 })
+    }
+
+    /// Reads the script from the file or from the standard input if `path` is `"-"`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = if path == Path::new("-") {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+        else {
+            fs::read_to_string(path)?
+        };
+        Self::parse(&text)
+    }
+
+    /// Returns the command to execute before the next frame or `None` if the script
+    /// waits for the frames to pass or has ended.
+    pub fn next_command(&mut self) -> Option<Command> {
+        if self.wait != 0 {
+            return None
+        }
+        match self.commands.pop_front()? {
+            Command::Wait(frames) => {
+                self.wait = frames.get();
+                None
+            }
+            command => Some(command)
+        }
+    }
+
+    /// Counts the emulated frame.
+    pub fn frame_passed(&mut self) {
+        self.wait = self.wait.saturating_sub(1);
+    }
+
+    /// Returns `true` if all the commands were executed.
+    pub fn is_finished(&self) -> bool {
+        self.wait == 0 && self.commands.is_empty()
+    }
+}
+
+/// Returns the key by its name, case insensitive.
+pub fn parse_key(name: &str) -> Option<Key> {
+    KEY_NAMES.iter().find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+                    .map(|&(_, key)| key)
+}
+
+fn parse_line(line: &str, commands: &mut VecDeque<Command>) -> Result<(), String> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    let (name, args) = match line.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (line, "")
+    };
+    let command = match name {
+        "" => return Ok(()),
+        "wait" => match args.parse().ok().and_then(NonZeroU32::new) {
+            Some(frames) => Command::Wait(frames),
+            None => return Err(format!("not a number of frames: \"{}\"", args))
+        },
+        "press"|"release" => {
+            let keys = parse_keys(args)?;
+            if name == "press" { Command::Press(keys) } else { Command::Release(keys) }
+        }
+        "type" => {
+            for ch in args.chars() {
+                let keys = char_keys(ch).ok_or_else(|| format!("can't type: '{}'", ch))?;
+                commands.push_back(Command::Press(keys.clone()));
+                commands.push_back(Command::Wait(NonZeroU32::new(TYPE_HOLD_FRAMES).unwrap()));
+                commands.push_back(Command::Release(keys));
+                commands.push_back(Command::Wait(NonZeroU32::new(TYPE_GAP_FRAMES).unwrap()));
+            }
+            return Ok(())
+        }
+        "screenshot"|"screenshot-screen" if !args.is_empty() => {
+            Command::Screenshot { path: args.into(), no_border: name == "screenshot-screen" }
+        }
+        "record-audio" if !args.is_empty() => Command::RecordAudio(args.into()),
+        "stop-audio" if args.is_empty() => Command::StopAudio,
+        "model" => match args {
+            "16" => Command::Model(16),
+            "48" => Command::Model(48),
+            "128" => Command::Model(128),
+            _ => return Err(format!("unknown model: \"{}\", choose from: 16|48|128", args))
+        },
+        "reset" => match args {
+            ""|"hard" => Command::Reset { hard: true },
+            "soft" => Command::Reset { hard: false },
+            _ => return Err(format!("unknown reset: \"{}\", choose from: hard|soft", args))
+        },
+        "nmi" if args.is_empty() => Command::Nmi,
+        "tape" => Command::Tape(match args {
+            "" => return Err("expected a TAP file or play|stop|rewind|next|prev|eject".into()),
+            "play" => TapeCommand::Play,
+            "stop" => TapeCommand::Stop,
+            "rewind" => TapeCommand::Rewind,
+            "next" => TapeCommand::Next,
+            "prev" => TapeCommand::Prev,
+            "eject" => TapeCommand::Eject,
+            path => TapeCommand::Insert(path.into())
+        }),
+        "exit" if args.is_empty() => Command::Exit,
+        _ => return Err(format!("unknown command: \"{}\"", line))
+    };
+    commands.push_back(command);
+    Ok(())
+}
+
+fn parse_keys(args: &str) -> Result<Vec<Key>, String> {
+    let keys = args.split_whitespace()
+                   .map(|name| parse_key(name).ok_or_else(|| format!("unknown key: \"{}\"", name)))
+                   .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("expected the key names".into())
+    }
+    Ok(keys)
+}
+
+// the keys pressed to type the character
+fn char_keys(ch: char) -> Option<Vec<Key>> {
+    let name = ch.to_ascii_uppercase().to_string();
+    let key = match ch {
+        ' ' => Key::Space,
+        'a'..='z'|'A'..='Z' => parse_key(&name)?,
+        '0'..='9' => parse_key(&format!("Key{}", name))?,
+        _ => return None
+    };
+    if ch.is_ascii_uppercase() {
+        Some(vec![Key::LeftShift, key])
+    }
+    else {
+        Some(vec![key])
+    }
+}
+
+static KEY_NAMES: &[(&str, Key)] = &[
+    ("A", Key::A), ("B", Key::B), ("C", Key::C), ("D", Key::D), ("E", Key::E),
+    ("F", Key::F), ("G", Key::G), ("H", Key::H), ("I", Key::I), ("J", Key::J),
+    ("K", Key::K), ("L", Key::L), ("M", Key::M), ("N", Key::N), ("O", Key::O),
+    ("P", Key::P), ("Q", Key::Q), ("R", Key::R), ("S", Key::S), ("T", Key::T),
+    ("U", Key::U), ("V", Key::V), ("W", Key::W), ("X", Key::X), ("Y", Key::Y),
+    ("Z", Key::Z),
+    ("Key0", Key::Key0), ("Key1", Key::Key1), ("Key2", Key::Key2), ("Key3", Key::Key3),
+    ("Key4", Key::Key4), ("Key5", Key::Key5), ("Key6", Key::Key6), ("Key7", Key::Key7),
+    ("Key8", Key::Key8), ("Key9", Key::Key9),
+    ("Enter", Key::Enter), ("Space", Key::Space), ("Backspace", Key::Backspace),
+    ("LeftShift", Key::LeftShift), ("RightShift", Key::RightShift),
+    ("LeftCtrl", Key::LeftCtrl), ("RightCtrl", Key::RightCtrl),
+    ("Up", Key::Up), ("Down", Key::Down), ("Left", Key::Left), ("Right", Key::Right),
+    ("Comma", Key::Comma), ("Period", Key::Period), ("Semicolon", Key::Semicolon),
+    ("Apostrophe", Key::Apostrophe), ("Minus", Key::Minus), ("Equal", Key::Equal),
+    ("Slash", Key::Slash)
+];
@@ -0,0 +1,129 @@
+//! Settings persisted between the runs of the emulator.
+//!
+//! The settings are kept as `key = value` lines in a text file in the user's
+//! configuration directory. Lines starting with `#` are ignored.
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use log::warn;
+
+/// The name of the directory created in the user's configuration directory.
+pub const APP_DIR_NAME: &str = "spectrusty-tutorial";
+/// The name of the settings file.
+pub const SETTINGS_FILE_NAME: &str = "settings.txt";
+
+/// The key-value settings.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    path: Option<PathBuf>,
+    values: BTreeMap<String, String>
+}
+
+impl Settings {
+    /// Loads the settings from the file in the user's configuration directory.
+    ///
+    /// Missing or unreadable settings are reported and the defaults are used instead.
+    pub fn load() -> Self {
+        match config_dir() {
+            Some(dir) => Self::load_from(dir.join(APP_DIR_NAME).join(SETTINGS_FILE_NAME)),
+            None => {
+                warn!("No configuration directory, the settings won't be saved");
+                Settings::default()
+            }
+        }
+    }
+
+    /// Loads the settings from the file at `path`, a missing file gives the empty settings.
+    pub fn load_from<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let values = match fs::read_to_string(&path) {
+            Ok(text) => parse_settings(&text),
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    warn!("Couldn't read the settings: {} {}", path.display(), err);
+                }
+                BTreeMap::new()
+            }
+        };
+        Settings { path: Some(path), values }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Returns the parsed value, the value that can't be parsed is reported and ignored.
+    pub fn parse<T: FromStr>(&self, key: &str) -> Option<T> {
+        let value = self.get(key)?;
+        let res = value.parse().ok();
+        if res.is_none() {
+            warn!("Invalid setting: {} = {}", key, value);
+        }
+        res
+    }
+
+    pub fn set<K: Into<String>, V: ToString>(&mut self, key: K, value: V) {
+        self.values.insert(key.into(), value.to_string());
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+
+    /// Writes the settings to the file, creating its directory if needed.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return Ok(())
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        for (key, value) in self.values.iter() {
+            writeln!(file, "{} = {}", key, value)?;
+        }
+        file.flush()
+    }
+
+    /// Sets the value and saves the settings, reporting any error.
+    pub fn store<K: Into<String>, V: ToString>(&mut self, key: K, value: V) {
+        self.set(key, value);
+        if let Err(err) = self.save() {
+            warn!("Couldn't save the settings: {}", err);
+        }
+    }
+}
+
+fn parse_settings(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+// the user's configuration directory
+fn config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    }
+    else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    }
+    else {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }
+}
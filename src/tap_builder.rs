@@ -0,0 +1,416 @@
+//! Builds TAP files with a generated BASIC loader followed by CODE blocks.
+//!
+//! The source is either a directory or a manifest file.
+//!
+//! In a directory each file named `NAME@ADDRESS.EXT` becomes a CODE block loaded
+//! at `ADDRESS`, files with the `.scr` extension are loaded at 16384 by default.
+//! The blocks are ordered by file names. If the directory contains `manifest.txt`
+//! it is used instead.
+//!
+//! The manifest is a text file with a single directive per line:
+//!
+//! ```text
+//! # a comment
+//! loader NAME [LINE]         # the loader program name and its autostart line
+//! code FILE ADDRESS [NAME]   # a CODE block loaded at ADDRESS
+//! clear ADDRESS              # the CLEAR address, defaults to below the lowest block
+//! run ADDRESS                # the RANDOMIZE USR address, defaults to the first block
+//! ```
+//!
+//! The blocks loaded into the screen memory are not taken into account for the default
+//! CLEAR and RANDOMIZE USR addresses.
+//!
+//! Addresses can be decimal or hexadecimal with the `0x` or `$` prefix.
+//! File paths are relative to the manifest.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The name of the manifest file looked up in the source directory.
+pub const MANIFEST_NAME: &str = "manifest.txt";
+
+const HEAD_BLOCK_FLAG: u8 = 0x00;
+const DATA_BLOCK_FLAG: u8 = 0xFF;
+const PROGRAM_TYPE: u8 = 0;
+const CODE_TYPE: u8 = 3;
+const SCREEN_ADDRESS: u16 = 16384;
+const SCREEN_END: u16 = 16384 + 6912;
+const DEFAULT_AUTOSTART: u16 = 10;
+// the TAP chunk length includes the flag and the checksum bytes
+const MAX_BLOCK_LENGTH: usize = u16::MAX as usize - 2;
+
+// BASIC tokens
+const TOKEN_CODE: u8 = 0xAF;
+const TOKEN_VAL: u8 = 0xB0;
+const TOKEN_USR: u8 = 0xC0;
+const TOKEN_LOAD: u8 = 0xEF;
+const TOKEN_RANDOMIZE: u8 = 0xF9;
+const TOKEN_CLEAR: u8 = 0xFD;
+
+/// A single CODE block.
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    pub name: String,
+    pub address: u16,
+    pub data: Vec<u8>
+}
+
+/// The content of the TAP file to be built.
+#[derive(Debug, Clone)]
+pub struct TapProject {
+    /// The name of the BASIC loader.
+    pub name: String,
+    /// The autostart line of the BASIC loader.
+    pub autostart: u16,
+    /// The CLEAR address, if `None` just below the lowest CODE block above the screen.
+    pub clear: Option<u16>,
+    /// The RANDOMIZE USR address, if `None` the address of the first CODE block
+    /// above the screen.
+    pub run: Option<u16>,
+    pub blocks: Vec<CodeBlock>
+}
+
+impl TapProject {
+    /// Reads the project from the directory or from the manifest file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let manifest = path.join(MANIFEST_NAME);
+            if manifest.is_file() {
+                Self::from_manifest(manifest)
+            }
+            else {
+                Self::from_dir(path)
+            }
+        }
+        else {
+            Self::from_manifest(path)
+        }
+    }
+
+    /// Reads the project from the directory with `NAME@ADDRESS.EXT` files.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+                                        .map(|entry| entry.map(|e| e.path()))
+                                        .collect::<io::Result<_>>()?;
+        paths.sort();
+        let mut blocks = Vec::new();
+        for path in paths.iter().filter(|p| p.is_file()) {
+            let stem = file_stem(path);
+            let (name, address) = match stem.rfind('@') {
+                Some(pos) => (&stem[..pos], parse_address(&stem[pos + 1..])?),
+                None if has_extension(path, "scr") => (stem, SCREEN_ADDRESS),
+                None => continue
+            };
+            blocks.push(CodeBlock { name: name.to_string(), address, data: fs::read(path)? });
+        }
+        if blocks.is_empty() {
+            return Err(invalid_data(format!("no NAME@ADDRESS files found in: {}", dir.display())))
+        }
+        let name = dir.file_name().and_then(|n| n.to_str()).unwrap_or("loader");
+        Ok(TapProject {
+            name: name.to_string(),
+            autostart: DEFAULT_AUTOSTART,
+            clear: None,
+            run: None,
+            blocks
+        })
+    }
+
+    /// Reads the project from the manifest file.
+    pub fn from_manifest<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut project = TapProject {
+            name: file_stem(path).to_string(),
+            autostart: DEFAULT_AUTOSTART,
+            clear: None,
+            run: None,
+            blocks: Vec::new()
+        };
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let err = |msg: &str| invalid_data(format!("{}:{}: {}", path.display(), index + 1, msg));
+            match words.as_slice() {
+                [] => {}
+                ["loader", name, rest @ ..] if rest.len() <= 1 => {
+                    project.name = name.to_string();
+                    if let Some(line) = rest.first() {
+                        project.autostart = line.parse().ok().filter(|&n| n < 10000)
+                                                .ok_or_else(|| err("invalid line number"))?;
+                    }
+                }
+                ["code", file, address, rest @ ..] if rest.len() <= 1 => {
+                    let file = base.join(file);
+                    let name = rest.first().copied().unwrap_or_else(|| file_stem(&file));
+                    project.blocks.push(CodeBlock {
+                        name: name.to_string(),
+                        address: parse_address(address)?,
+                        data: fs::read(&file)?
+                    });
+                }
+                ["clear", address] => { project.clear = Some(parse_address(address)?); }
+                ["run", address] => { project.run = Some(parse_address(address)?); }
+                _ => return Err(err("unknown directive"))
+            }
+        }
+        if project.blocks.is_empty() {
+            return Err(invalid_data(format!("no code blocks in: {}", path.display())))
+        }
+        Ok(project)
+    }
+
+    /// Returns the tokenized BASIC loader program.
+    pub fn loader_program(&self) -> Vec<u8> {
+        // the blocks below the end of the screen memory don't need the CLEAR
+        let clear = self.clear.or_else(||
+            self.blocks.iter().map(|b| b.address).filter(|&a| a >= SCREEN_END)
+                       .min().map(|a| a - 1)
+        );
+        let run = self.run.unwrap_or_else(||
+            self.blocks.iter().find(|b| b.address >= SCREEN_END)
+                       .or_else(|| self.blocks.first())
+                       .map(|b| b.address).unwrap_or(0)
+        );
+        // CLEAR VAL "clear": LOAD ""CODE : ... : RANDOMIZE USR VAL "run"
+        let mut line = Vec::new();
+        if let Some(clear) = clear {
+            line.extend_from_slice(&[TOKEN_CLEAR, TOKEN_VAL]);
+            push_quoted(&mut line, &clear.to_string());
+            line.push(b':');
+        }
+        for _ in &self.blocks {
+            line.push(TOKEN_LOAD);
+            push_quoted(&mut line, "");
+            line.extend_from_slice(&[TOKEN_CODE, b':']);
+        }
+        line.extend_from_slice(&[TOKEN_RANDOMIZE, TOKEN_USR, TOKEN_VAL]);
+        push_quoted(&mut line, &run.to_string());
+        line.push(0x0D);
+
+        let mut program = Vec::with_capacity(line.len() + 4);
+        program.extend_from_slice(&self.autostart.to_be_bytes());
+        program.extend_from_slice(&(line.len() as u16).to_le_bytes());
+        program.extend_from_slice(&line);
+        program
+    }
+
+    /// Writes the loader and the CODE blocks as TAP chunks.
+    pub fn write_tap<W: Write>(&self, mut wr: W) -> io::Result<()> {
+        for block in &self.blocks {
+            if block.data.len() > MAX_BLOCK_LENGTH {
+                return Err(invalid_data(format!("the CODE block: {} is too long: {} bytes",
+                                                block.name, block.data.len())))
+            }
+            if block.address as usize + block.data.len() > 0x10000 {
+                return Err(invalid_data(format!("the CODE block: {} doesn't fit in memory",
+                                                block.name)))
+            }
+        }
+        let program = self.loader_program();
+        let length = program.len() as u16;
+        write_chunk(&mut wr, &header(PROGRAM_TYPE, &self.name, length, self.autostart, length))?;
+        write_chunk(&mut wr, &data(&program))?;
+        for block in &self.blocks {
+            let length = block.data.len() as u16;
+            write_chunk(&mut wr, &header(CODE_TYPE, &block.name, length, block.address, 0x8000))?;
+            write_chunk(&mut wr, &data(&block.data))?;
+        }
+        wr.flush()
+    }
+}
+
+fn push_quoted(line: &mut Vec<u8>, text: &str) {
+    line.push(b'"');
+    line.extend_from_slice(text.as_bytes());
+    line.push(b'"');
+}
+
+fn header(block_type: u8, name: &str, length: u16, par1: u16, par2: u16) -> Vec<u8> {
+    let mut chunk = vec![HEAD_BLOCK_FLAG, block_type];
+    let mut name_bytes = [b' '; 10];
+    for (t, s) in name_bytes.iter_mut().zip(name.bytes().filter(|b| b.is_ascii())) {
+        *t = s;
+    }
+    chunk.extend_from_slice(&name_bytes);
+    chunk.extend_from_slice(&length.to_le_bytes());
+    chunk.extend_from_slice(&par1.to_le_bytes());
+    chunk.extend_from_slice(&par2.to_le_bytes());
+    push_checksum(chunk)
+}
+
+fn data(data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 2);
+    chunk.push(DATA_BLOCK_FLAG);
+    chunk.extend_from_slice(data);
+    push_checksum(chunk)
+}
+
+fn push_checksum(mut chunk: Vec<u8>) -> Vec<u8> {
+    let checksum = chunk.iter().fold(0, |acc, b| acc ^ b);
+    chunk.push(checksum);
+    chunk
+}
+
+fn write_chunk<W: Write>(wr: &mut W, chunk: &[u8]) -> io::Result<()> {
+    wr.write_all(&(chunk.len() as u16).to_le_bytes())?;
+    wr.write_all(chunk)
+}
+
+fn parse_address(s: &str) -> io::Result<u16> {
+    let res = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    }
+    else {
+        s.parse()
+    };
+    res.map_err(|_| invalid_data(format!("invalid address: \"{}\"", s)))
+}
+
+fn file_stem(path: &Path) -> &str {
+    path.file_stem().and_then(|s| s.to_str()).unwrap_or("")
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension().and_then(|e| e.to_str())
+                    .map(|e| e.eq_ignore_ascii_case(ext))
+                    .unwrap_or(false)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    // a fresh directory for the test files
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tap_builder-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // splits the TAP file into the chunks, verifying their checksums
+    fn chunks(mut tap: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        while !tap.is_empty() {
+            let len = u16::from_le_bytes([tap[0], tap[1]]) as usize;
+            let chunk = &tap[2..2 + len];
+            assert_eq!(chunk.iter().fold(0, |acc, b| acc ^ b), 0, "invalid checksum");
+            chunks.push(chunk[..len - 1].to_vec());
+            tap = &tap[2 + len..];
+        }
+        chunks
+    }
+
+    // returns the type, the name, the length and both parameters of the header
+    fn parse_header(chunk: &[u8]) -> (u8, &str, u16, u16, u16) {
+        assert_eq!(chunk.len(), 18);
+        assert_eq!(chunk[0], HEAD_BLOCK_FLAG);
+        let word = |pos: usize| u16::from_le_bytes([chunk[pos], chunk[pos + 1]]);
+        (chunk[1], core::str::from_utf8(&chunk[2..12]).unwrap(), word(12), word(14), word(16))
+    }
+
+    #[test]
+    fn tap_is_built_from_manifest() {
+        let dir = test_dir("manifest");
+        fs::write(dir.join("game.bin"), [1, 2, 3, 4, 5]).unwrap();
+        fs::write(dir.join("title.scr"), vec![0x55; 6912]).unwrap();
+        fs::write(dir.join("game.txt"), "\
+            # the test manifest\n\
+            loader Game 20\n\
+            code title.scr $4000 screen\n\
+            code game.bin 0x8000   # the code\n\
+            run 32770\n").unwrap();
+        let project = TapProject::open(dir.join("game.txt")).unwrap();
+        let mut tap = Vec::new();
+        project.write_tap(&mut tap).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let chunks = chunks(&tap);
+        assert_eq!(chunks.len(), 6);
+        let program = project.loader_program();
+        assert_eq!(parse_header(&chunks[0]),
+                   (PROGRAM_TYPE, "Game      ", program.len() as u16, 20, program.len() as u16));
+        assert_eq!(chunks[1][0], DATA_BLOCK_FLAG);
+        assert_eq!(&chunks[1][1..], &program[..]);
+        assert_eq!(parse_header(&chunks[2]), (CODE_TYPE, "screen    ", 6912, 16384, 0x8000));
+        assert_eq!(&chunks[3][1..], &[0x55; 6912][..]);
+        assert_eq!(parse_header(&chunks[4]), (CODE_TYPE, "game      ", 5, 0x8000, 0x8000));
+        assert_eq!(&chunks[5][..], &[DATA_BLOCK_FLAG, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn loader_program_is_tokenized() {
+        let project = TapProject {
+            name: "test".into(),
+            autostart: 10,
+            clear: None,
+            run: None,
+            blocks: vec![
+                CodeBlock { name: "screen".into(), address: SCREEN_ADDRESS, data: vec![0; 6912] },
+                CodeBlock { name: "code".into(), address: 24576, data: vec![0xC9] }
+            ]
+        };
+        let program = project.loader_program();
+        // the line number is big endian, the length little endian
+        assert_eq!(&program[..2], &[0, 10]);
+        assert_eq!(u16::from_le_bytes([program[2], program[3]]) as usize, program.len() - 4);
+        let mut line = vec![TOKEN_CLEAR, TOKEN_VAL];
+        line.extend_from_slice(b"\"24575\":");
+        for _ in 0..2 {
+            line.extend_from_slice(&[TOKEN_LOAD, b'"', b'"', TOKEN_CODE, b':']);
+        }
+        line.extend_from_slice(&[TOKEN_RANDOMIZE, TOKEN_USR, TOKEN_VAL]);
+        line.extend_from_slice(b"\"24576\"\r");
+        assert_eq!(&program[4..], &line[..]);
+    }
+
+    #[test]
+    fn blocks_are_read_from_dir() {
+        let dir = test_dir("dir");
+        fs::write(dir.join("b_code@0x6000.bin"), [0xC9]).unwrap();
+        fs::write(dir.join("a_title.scr"), [0; 6912]).unwrap();
+        fs::write(dir.join("readme.txt"), "ignored").unwrap();
+        let project = TapProject::open(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let blocks: Vec<_> = project.blocks.iter().map(|b| (b.name.as_str(), b.address)).collect();
+        assert_eq!(blocks, [("a_title", SCREEN_ADDRESS), ("b_code", 0x6000)]);
+        assert_eq!(project.autostart, DEFAULT_AUTOSTART);
+    }
+
+    #[test]
+    fn invalid_manifest_is_rejected() {
+        let dir = test_dir("invalid");
+        fs::write(dir.join("code.bin"), [0]).unwrap();
+        fs::write(dir.join(MANIFEST_NAME), "code code.bin 32768\nload x\n").unwrap();
+        let err = TapProject::open(&dir).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with(":2: unknown directive"), "{}", err);
+    }
+
+    #[test]
+    fn block_must_fit_in_memory() {
+        let mut project = TapProject {
+            name: "test".into(),
+            autostart: 10,
+            clear: None,
+            run: None,
+            blocks: vec![CodeBlock { name: "code".into(), address: 0xFFFF, data: vec![0; 2] }]
+        };
+        assert!(project.write_tap(io::sink()).is_err());
+        project.blocks[0].address = 0;
+        project.blocks[0].data = vec![0; MAX_BLOCK_LENGTH + 1];
+        assert!(project.write_tap(io::sink()).is_err());
+        project.blocks[0].data.pop();
+        assert!(project.write_tap(io::sink()).is_ok());
+    }
+}
@@ -0,0 +1,257 @@
+//! TAPE file backends and the splitter of recorded TAP chunks.
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// A TAPE file that is either a regular file, a read-only in-memory copy
+/// of an archive entry or a shared memory buffer.
+#[derive(Debug)]
+pub enum TapeFile {
+    File(File),
+    Archived(Cursor<Vec<u8>>),
+    Memory(SharedBuffer)
+}
+
+impl TapeFile {
+    pub fn is_archived(&self) -> bool {
+        matches!(self, TapeFile::Archived(..))
+    }
+}
+
+impl From<File> for TapeFile {
+    fn from(file: File) -> Self {
+        TapeFile::File(file)
+    }
+}
+
+impl Read for TapeFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            TapeFile::File(file) => file.read(buf),
+            TapeFile::Archived(cursor) => cursor.read(buf),
+            TapeFile::Memory(shared) => shared.read(buf)
+        }
+    }
+}
+
+impl Write for TapeFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TapeFile::File(file) => file.write(buf),
+            TapeFile::Archived(..) => Err(read_only_error()),
+            TapeFile::Memory(shared) => shared.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TapeFile::File(file) => file.flush(),
+            TapeFile::Archived(..) => Ok(()),
+            TapeFile::Memory(shared) => shared.flush()
+        }
+    }
+}
+
+impl Seek for TapeFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            TapeFile::File(file) => file.seek(pos),
+            TapeFile::Archived(cursor) => cursor.seek(pos),
+            TapeFile::Memory(shared) => shared.seek(pos)
+        }
+    }
+}
+
+fn read_only_error() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied,
+                   "a TAPE opened from a ZIP archive can't be recorded to")
+}
+
+/// An in-memory file with the content shared between clones.
+///
+/// Each clone has its own cursor position.
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer {
+    data: Rc<RefCell<Vec<u8>>>,
+    pos: u64
+}
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        SharedBuffer::default()
+    }
+    /// Calls `f` with the current content of the buffer.
+    pub fn with_data<T, F: FnOnce(&[u8]) -> T>(&self, f: F) -> T {
+        f(&self.data.borrow())
+    }
+}
+
+impl Read for SharedBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.borrow();
+        let start = (self.pos as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.data.borrow_mut();
+        let start = self.pos as usize;
+        let end = start + buf.len();
+        if end > data.len() {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SharedBuffer {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(pos) => {
+                self.pos = pos;
+                return Ok(pos)
+            }
+            SeekFrom::End(offset) => (self.data.borrow().len() as u64, offset),
+            SeekFrom::Current(offset) => (self.pos, offset)
+        };
+        let pos = if offset >= 0 {
+            base.checked_add(offset as u64)
+        }
+        else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                       "invalid seek to a negative or overflowing position"))
+        }
+    }
+}
+
+const HEAD_BLOCK_FLAG: u8 = 0x00;
+const HEADER_CHUNK_SIZE: usize = 19;
+
+/// Distributes the TAP chunks recorded into the [SharedBuffer] into separate
+/// TAP files in the output directory.
+///
+/// A new file, named after the program name found in the header, is started each
+/// time a header chunk is found.
+#[derive(Debug)]
+pub struct TapSplitter {
+    dir: PathBuf,
+    buffer: SharedBuffer,
+    offset: usize,
+    file: Option<File>
+}
+
+impl TapSplitter {
+    /// Creates a new splitter writing files to `dir` and returns it together with
+    /// the [TapeFile] the TAP chunks should be recorded to.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> (TapSplitter, TapeFile) {
+        let buffer = SharedBuffer::new();
+        let splitter = TapSplitter {
+            dir: dir.into(),
+            buffer: buffer.clone(),
+            offset: 0,
+            file: None
+        };
+        (splitter, TapeFile::Memory(buffer))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Writes the complete TAP chunks recorded since the last call to the
+    /// output files. Returns the paths of the newly created files.
+    pub fn split_chunks(&mut self) -> io::Result<Vec<PathBuf>> {
+        let buffer = self.buffer.clone();
+        buffer.with_data(|data| {
+            let mut created = Vec::new();
+            while let Some(chunk) = next_chunk(&data[self.offset..]) {
+                let size = chunk.len();
+                if is_header(chunk) || self.file.is_none() {
+                    let path = self.create_file(chunk)?;
+                    created.push(path);
+                }
+                let file = self.file.as_mut().unwrap();
+                file.write_all(&(size as u16).to_le_bytes())?;
+                file.write_all(chunk)?;
+                self.offset += 2 + size;
+            }
+            if let Some(file) = self.file.as_mut() {
+                file.flush()?;
+            }
+            Ok(created)
+        })
+    }
+
+    fn create_file(&mut self, chunk: &[u8]) -> io::Result<PathBuf> {
+        let name = if is_header(chunk) {
+            sanitize_name(&chunk[2..12])
+        }
+        else {
+            String::new()
+        };
+        let name = if name.is_empty() { "headerless".into() } else { name };
+        let mut index = 0;
+        loop {
+            let mut path = self.dir.clone();
+            if index == 0 {
+                path.push(format!("{}.tap", name));
+            }
+            else {
+                path.push(format!("{}-{}.tap", name, index));
+            }
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    self.file = Some(file);
+                    return Ok(path)
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => index += 1,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+}
+
+// returns the next complete chunk, the chunk being recorded has its size still zeroed
+fn next_chunk(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 2 {
+        return None
+    }
+    let size = u16::from_le_bytes([data[0], data[1]]) as usize;
+    if size == 0 || data.len() < 2 + size {
+        return None
+    }
+    Some(&data[2..2 + size])
+}
+
+fn is_header(chunk: &[u8]) -> bool {
+    chunk.len() == HEADER_CHUNK_SIZE && chunk[0] == HEAD_BLOCK_FLAG
+}
+
+// makes a file name from the ZX Spectrum program name
+fn sanitize_name(name: &[u8]) -> String {
+    let name: String = name.iter().map(|&b| match b {
+        b'0'..=b'9'|b'a'..=b'z'|b'A'..=b'Z'|b'-'|b'.' => b as char,
+        _ => '_'
+    }).collect();
+    name.trim_matches(|c| c == '_' || c == '.').to_string()
+}
@@ -0,0 +1,148 @@
+//! The TurboSound bus device with the second AY-3-8912 chip for the 128k model.
+//!
+//! The device is placed in front of the built-in AY of the 128k. Writing `0xFF` or `0xFE`
+//! to the AY register port `0xFFFD` selects the first (built-in) or the second chip,
+//! the other AY port accesses go to the selected chip.
+//!
+//! The audio of both chips is rendered into the same channels.
+use core::num::NonZeroU16;
+use spectrusty::audio::{AmpLevels, Blep};
+use spectrusty::bus::{BusDevice, NullDevice, ay::{Ay3_891xMelodik, AyAudioBusDevice}};
+use spectrusty::clock::FTs;
+
+// the AY ports of the 128k are decoded by A15, A14 and A1
+const AY_PORT_MASK: u16 = 0xC002;
+// 0xFFFD
+const AY_REGISTER_PORT_BITS: u16 = 0xC000;
+// 0xBFFD
+const AY_DATA_PORT_BITS: u16 = 0x8000;
+// the values written to the register port that select the chip by the lowest bit
+const CHIP_SELECT_MASK: u8 = 0xFE;
+
+/// The second AY chip.
+pub type TurboSoundChip = Ay3_891xMelodik<NullDevice<FTs>>;
+
+/// The TurboSound bus device in front of the built-in AY bus device `D`.
+///
+/// The device passes all the accesses to `D` until it's enabled.
+#[derive(Debug)]
+pub struct TurboSoundBusDevice<D> {
+    // the second chip, present if the TurboSound is enabled
+    second: Option<TurboSoundChip>,
+    // is the second chip selected?
+    second_selected: bool,
+    bus: D
+}
+
+impl<D: Default> Default for TurboSoundBusDevice<D> {
+    fn default() -> Self {
+        TurboSoundBusDevice { second: None, second_selected: false, bus: D::default() }
+    }
+}
+
+impl<D> TurboSoundBusDevice<D> {
+    pub fn is_enabled(&self) -> bool {
+        self.second.is_some()
+    }
+
+    /// Adds or removes the second chip, the first chip is selected.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled != self.is_enabled() {
+            self.second = if enabled { Some(TurboSoundChip::default()) } else { None };
+        }
+        self.second_selected = false;
+    }
+
+    /// Returns the second chip if the TurboSound is enabled.
+    pub fn second_chip_ref(&self) -> Option<&TurboSoundChip> {
+        self.second.as_ref()
+    }
+
+    // the second chip if it's present and selected
+    fn selected_second_mut(&mut self) -> Option<&mut TurboSoundChip> {
+        self.second.as_mut().filter(|_| self.second_selected)
+    }
+}
+
+impl<D> BusDevice for TurboSoundBusDevice<D>
+    where D: BusDevice<Timestamp=FTs>
+{
+    type Timestamp = FTs;
+    type NextDevice = D;
+
+    #[inline]
+    fn next_device_mut(&mut self) -> &mut Self::NextDevice {
+        &mut self.bus
+    }
+
+    #[inline]
+    fn next_device_ref(&self) -> &Self::NextDevice {
+        &self.bus
+    }
+
+    #[inline]
+    fn into_next_device(self) -> Self::NextDevice {
+        self.bus
+    }
+
+    fn reset(&mut self, timestamp: Self::Timestamp) {
+        self.second_selected = false;
+        if let Some(second) = self.second.as_mut() {
+            second.reset(timestamp);
+        }
+        self.bus.reset(timestamp)
+    }
+
+    fn update_timestamp(&mut self, timestamp: Self::Timestamp) {
+        if let Some(second) = self.second.as_mut() {
+            second.update_timestamp(timestamp);
+        }
+        self.bus.update_timestamp(timestamp)
+    }
+
+    fn next_frame(&mut self, eof_timestamp: Self::Timestamp) {
+        if let Some(second) = self.second.as_mut() {
+            second.next_frame(eof_timestamp);
+        }
+        self.bus.next_frame(eof_timestamp)
+    }
+
+    fn read_io(&mut self, port: u16, timestamp: Self::Timestamp) -> Option<(u8, Option<NonZeroU16>)> {
+        if port & AY_PORT_MASK == AY_REGISTER_PORT_BITS {
+            if let Some(second) = self.selected_second_mut() {
+                return second.read_io(port, timestamp)
+            }
+        }
+        self.bus.read_io(port, timestamp)
+    }
+
+    fn write_io(&mut self, port: u16, data: u8, timestamp: Self::Timestamp) -> Option<u16> {
+        if self.second.is_some() {
+            let port_bits = port & AY_PORT_MASK;
+            if port_bits == AY_REGISTER_PORT_BITS && data & CHIP_SELECT_MASK == CHIP_SELECT_MASK {
+                self.second_selected = data & 1 == 0;
+                return Some(0)
+            }
+            if port_bits == AY_REGISTER_PORT_BITS || port_bits == AY_DATA_PORT_BITS {
+                if let Some(second) = self.selected_second_mut() {
+                    return second.write_io(port, data, timestamp)
+                }
+            }
+        }
+        self.bus.write_io(port, data, timestamp)
+    }
+}
+
+impl<D> AyAudioBusDevice for TurboSoundBusDevice<D>
+    where D: AyAudioBusDevice + BusDevice<Timestamp=FTs>
+{
+    fn render_ay_audio<L, A>(&mut self, blep: &mut A, end_ts: FTs, frame_tstates: FTs, chans: [usize; 3])
+        where L: AmpLevels<A::SampleDelta>,
+              A: Blep
+    {
+        self.bus.render_ay_audio::<L, A>(blep, end_ts, frame_tstates, chans);
+        if let Some(second) = self.second.as_mut() {
+            second.render_ay_audio::<L, A>(blep, end_ts, frame_tstates, chans);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! The ULAplus bus device with the 64 colour palette.
+//!
+//! The device listens to the register port `0xBF3B` and the data port `0xFF3B`.
+//! The register port selects either a palette entry or the mode register, the data
+//! port writes or reads the selected register.
+//!
+//! The palette entries are `GGGRRRBB` bytes. When the palette mode is enabled,
+//! the FLASH and BRIGHT attribute bits select one of the four 16 colour palettes:
+//! 8 INK colours followed by 8 PAPER colours. The BORDER uses the PAPER colours
+//! of the first palette.
+//!
+//! The limitations:
+//!
+//! * in the palette mode the screen is rendered from the screen memory at the end of
+//!   the frame with the palette of that moment, so the raster effects, changing the
+//!   attributes or the palette in the middle of the frame, are not shown,
+//! * the palette and the mode register are not kept in any saved state, as the emulator
+//!   has no saved states, they last only until the emulator exits.
+use core::fmt;
+use core::num::NonZeroU16;
+use spectrusty::bus::BusDevice;
+
+/// The ULAplus register port.
+pub const ULAPLUS_REGISTER_PORT: u16 = 0xBF3B;
+/// The ULAplus data port.
+pub const ULAPLUS_DATA_PORT: u16 = 0xFF3B;
+/// The number of the palette entries.
+pub const ULAPLUS_PALETTE_SIZE: usize = 64;
+
+const GROUP_MASK: u8 = 0b1100_0000;
+const PALETTE_GROUP: u8 = 0b0000_0000;
+const MODE_GROUP: u8 = 0b0100_0000;
+const PALETTE_MODE_FLAG: u8 = 0b0000_0001;
+
+/// The ULAplus palette in the XRGB format.
+pub type UlaPlusColors = [u32; ULAPLUS_PALETTE_SIZE];
+
+/// The ULAplus bus device.
+#[derive(Clone)]
+pub struct UlaPlusBusDevice<D> {
+    register: u8,
+    mode: u8,
+    palette: [u8; ULAPLUS_PALETTE_SIZE],
+    bus: D
+}
+
+impl<D: Default> Default for UlaPlusBusDevice<D> {
+    fn default() -> Self {
+        UlaPlusBusDevice {
+            register: 0,
+            mode: 0,
+            palette: [0; ULAPLUS_PALETTE_SIZE],
+            bus: D::default()
+        }
+    }
+}
+
+impl<D> fmt::Debug for UlaPlusBusDevice<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UlaPlusBusDevice")
+         .field("register", &self.register)
+         .field("mode", &self.mode)
+         .field("palette", &&self.palette[..])
+         .finish()
+    }
+}
+
+impl<D> UlaPlusBusDevice<D> {
+    /// Returns `true` if the 64 colour palette mode is enabled.
+    pub fn is_palette_mode(&self) -> bool {
+        self.mode & PALETTE_MODE_FLAG != 0
+    }
+
+    /// Returns the palette entries as `GGGRRRBB` bytes.
+    pub fn palette(&self) -> &[u8; ULAPLUS_PALETTE_SIZE] {
+        &self.palette
+    }
+
+    /// Returns the palette converted to the XRGB format.
+    pub fn colors(&self) -> UlaPlusColors {
+        let mut colors = [0; ULAPLUS_PALETTE_SIZE];
+        for (color, &grb) in colors.iter_mut().zip(self.palette.iter()) {
+            *color = grb_to_xrgb(grb);
+        }
+        colors
+    }
+}
+
+impl<D> BusDevice for UlaPlusBusDevice<D>
+    where D: BusDevice
+{
+    type Timestamp = D::Timestamp;
+    type NextDevice = D;
+
+    #[inline]
+    fn next_device_mut(&mut self) -> &mut Self::NextDevice {
+        &mut self.bus
+    }
+
+    #[inline]
+    fn next_device_ref(&self) -> &Self::NextDevice {
+        &self.bus
+    }
+
+    #[inline]
+    fn into_next_device(self) -> Self::NextDevice {
+        self.bus
+    }
+
+    fn reset(&mut self, timestamp: Self::Timestamp) {
+        self.register = 0;
+        self.mode = 0;
+        self.palette = [0; ULAPLUS_PALETTE_SIZE];
+        self.bus.reset(timestamp)
+    }
+
+    fn read_io(&mut self, port: u16, timestamp: Self::Timestamp) -> Option<(u8, Option<NonZeroU16>)> {
+        if port == ULAPLUS_DATA_PORT {
+            let data = match self.register & GROUP_MASK {
+                PALETTE_GROUP => self.palette[(self.register & !GROUP_MASK) as usize],
+                MODE_GROUP => self.mode,
+                _ => !0
+            };
+            return Some((data, None))
+        }
+        self.bus.read_io(port, timestamp)
+    }
+
+    fn write_io(&mut self, port: u16, data: u8, timestamp: Self::Timestamp) -> Option<u16> {
+        match port {
+            ULAPLUS_REGISTER_PORT => {
+                self.register = data;
+                Some(0)
+            }
+            ULAPLUS_DATA_PORT => {
+                match self.register & GROUP_MASK {
+                    PALETTE_GROUP => self.palette[(self.register & !GROUP_MASK) as usize] = data,
+                    MODE_GROUP => self.mode = data,
+                    _ => {}
+                }
+                Some(0)
+            }
+            _ => self.bus.write_io(port, data, timestamp)
+        }
+    }
+}
+
+/// Converts the `GGGRRRBB` palette entry to the XRGB colour.
+pub fn grb_to_xrgb(grb: u8) -> u32 {
+    let g = (grb >> 5) as u32;
+    let r = (grb >> 2 & 0b111) as u32;
+    // the missing lowest blue bit is the OR of the other two
+    let b = (grb & 0b11) as u32;
+    let b = b << 1 | (b != 0) as u32;
+    let scale = |c: u32| c * 255 / 7;
+    scale(r) << 16 | scale(g) << 8 | scale(b)
+}
+
+/// The size of the screen memory with the bitmap and the attributes.
+pub const SCREEN_SIZE: usize = 6912;
+const ATTRS_OFFSET: usize = 6144;
+const SCREEN_WIDTH: usize = 256;
+const SCREEN_HEIGHT: usize = 192;
+
+/// Renders the frame with the ULAplus palette.
+///
+/// `pixels` should contain the palette indices of the rendered frame, of which only
+/// the BORDER colours are used. The screen area is rendered from the `screen` memory
+/// content, so any changes to the screen memory during the frame are not visible.
+pub fn render_ulaplus_frame(
+        pixels: &mut [u32],
+        width: usize,
+        height: usize,
+        screen: &[u8],
+        colors: &UlaPlusColors
+    )
+{
+    let left = width.saturating_sub(SCREEN_WIDTH) / 2;
+    let top = height.saturating_sub(SCREEN_HEIGHT) / 2;
+    for (y, line) in pixels.chunks_exact_mut(width).enumerate().take(height) {
+        let screen_y = y.wrapping_sub(top);
+        if screen_y >= SCREEN_HEIGHT || screen.len() < SCREEN_SIZE {
+            for pixel in line.iter_mut() {
+                *pixel = colors[8 + (*pixel as usize & 7)];
+            }
+            continue
+        }
+        let (left_border, rest) = line.split_at_mut(left.min(width));
+        let (screen_line, right_border) = rest.split_at_mut(SCREEN_WIDTH.min(rest.len()));
+        for pixel in left_border.iter_mut().chain(right_border.iter_mut()) {
+            *pixel = colors[8 + (*pixel as usize & 7)];
+        }
+        let bitmap_line = (screen_y & 0xC0) << 5 | (screen_y & 0x07) << 8 | (screen_y & 0x38) << 2;
+        let attr_line = ATTRS_OFFSET + (screen_y >> 3) * 32;
+        for (col, cell) in screen_line.chunks_mut(8).enumerate() {
+            let bitmap = screen[bitmap_line + col];
+            let attr = screen[attr_line + col] as usize;
+            // FLASH and BRIGHT select the palette
+            let base = (attr >> 6) * 16;
+            let ink = colors[base + (attr & 7)];
+            let paper = colors[base + 8 + (attr >> 3 & 7)];
+            for (bit, pixel) in cell.iter_mut().enumerate() {
+                *pixel = if bitmap & (0x80 >> bit) != 0 { ink } else { paper };
+            }
+        }
+    }
+}